@@ -0,0 +1,223 @@
+use std::fmt;
+
+use azalea::prelude::*;
+use sysx::io::log::*;
+use tokio::time::Instant;
+
+use crate::{
+    config::{Pattern, PatternCatalog},
+    navigation,
+    outbox::{self, Outgoing, Priority},
+    supervisor::BotSignal,
+    types::State,
+};
+
+/// Steps of the login flow after joining.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AuthState {
+    /// Not connected yet.
+    #[default]
+    Idle,
+    /// Waiting for the server to ask for `/reg` or `/login`.
+    AwaitingPrompt,
+    Registering,
+    LoggingIn,
+    Authenticated,
+    Failed,
+}
+
+impl fmt::Display for AuthState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            AuthState::Idle => "idle",
+            AuthState::AwaitingPrompt => "awaiting prompt",
+            AuthState::Registering => "registering",
+            AuthState::LoggingIn => "logging in",
+            AuthState::Authenticated => "authenticated",
+            AuthState::Failed => "failed",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Login flow driven by the server's chat: only the command the server asks for is sent,
+/// unanswered steps are retried after `auth.timeout` up to `auth.max_attempts` times.
+#[derive(Debug, Default)]
+pub struct AuthFlow {
+    state: AuthState,
+    /// When the current step started or its command was last sent.
+    since: Option<Instant>,
+    /// Commands sent in the current step.
+    attempts: u32,
+}
+
+impl AuthFlow {
+    pub fn state(&self) -> AuthState {
+        self.state
+    }
+
+    pub fn is_authenticated(&self) -> bool {
+        self.state == AuthState::Authenticated
+    }
+
+    /// What a server line means for the flow, `None` when nothing.
+    fn step(&self, patterns: &PatternCatalog, text: &str) -> Option<Step> {
+        let matches = |pattern| patterns.is_match(pattern, text);
+        match self.state {
+            AuthState::Idle | AuthState::Authenticated | AuthState::Failed => None,
+            _ if matches(Pattern::AuthSuccess) => Some(Step::Authenticated("logged in")),
+            _ if matches(Pattern::SessionRestored) => Some(Step::Authenticated("session restored")),
+            AuthState::LoggingIn if matches(Pattern::WrongPassword) => Some(Step::WrongPassword),
+            AuthState::AwaitingPrompt | AuthState::Registering if matches(Pattern::AlreadyRegistered) => {
+                Some(Step::Send(AuthState::LoggingIn, "already registered"))
+            }
+            AuthState::AwaitingPrompt if matches(Pattern::RegisterPrompt) => {
+                Some(Step::Send(AuthState::Registering, "register prompt"))
+            }
+            AuthState::AwaitingPrompt if matches(Pattern::LoginPrompt) => {
+                Some(Step::Send(AuthState::LoggingIn, "login prompt"))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// How a server line advances the flow.
+#[derive(Debug, PartialEq, Eq)]
+enum Step {
+    Authenticated(&'static str),
+    WrongPassword,
+    /// Move on to the step and send its command.
+    Send(AuthState, &'static str),
+}
+
+/// Starts the flow on a fresh connection.
+pub fn on_login(state: &State) {
+    let mut flow = state.auth.lock();
+    *flow = AuthFlow::default();
+    transition(state, &mut flow, AuthState::AwaitingPrompt, "joined");
+}
+
+/// Advances the flow on a server line. Messages are ignored once the flow is over.
+pub fn on_chat(bot: &Client, state: &State, text: &str) {
+    let patterns = state.config.read().patterns.clone();
+    let mut flow = state.auth.lock();
+    match flow.step(&patterns, text) {
+        Some(Step::Authenticated(why)) => authenticated(bot, state, &mut flow, why),
+        Some(Step::WrongPassword) => {
+            if flow.attempts >= state.config.read().auth.max_attempts {
+                failed(bot, state, &mut flow, "wrong password");
+            } else {
+                log!(INFO, "[{}] Auth: wrong password, trying again", portal(state));
+                send(bot, state, &mut flow, AuthState::LoggingIn);
+            }
+        }
+        Some(Step::Send(to, why)) => {
+            transition(state, &mut flow, to, why);
+            send(bot, state, &mut flow, to);
+        }
+        None => {}
+    }
+}
+
+/// Retries a step the server did not answer in time, or gives up.
+pub fn on_tick(bot: &Client, state: &State) {
+    let mut flow = state.auth.lock();
+    let Some(since) = flow.since else { return };
+    let auth = state.config.read().auth.clone();
+    if !matches!(flow.state, AuthState::AwaitingPrompt | AuthState::Registering | AuthState::LoggingIn)
+        || since.elapsed() < auth.timeout
+    {
+        return;
+    }
+
+    if flow.attempts >= auth.max_attempts {
+        let why = format!("no answer while {} after {} attempt(s)", flow.state, flow.attempts);
+        failed(bot, state, &mut flow, &why);
+        return;
+    }
+    log!(INFO, "[{}] Auth: timed out while {}, retrying", portal(state), flow.state);
+    match flow.state {
+        // The prompt may have been missed, most accounts already exist
+        AuthState::AwaitingPrompt => {
+            transition(state, &mut flow, AuthState::LoggingIn, "no prompt");
+            send(bot, state, &mut flow, AuthState::LoggingIn);
+        }
+        step => send(bot, state, &mut flow, step),
+    }
+}
+
+fn send(bot: &Client, state: &State, flow: &mut AuthFlow, step: AuthState) {
+    let password = state.config.read().bot.password.clone();
+    let command = match step {
+        AuthState::Registering => format!("/reg {password}"),
+        AuthState::LoggingIn => format!("/login {password}"),
+        _ => return,
+    };
+    outbox::replace(bot, state, Priority::Auth, Outgoing::Chat(command));
+    flow.attempts += 1;
+    flow.since = Some(Instant::now());
+}
+
+fn transition(state: &State, flow: &mut AuthFlow, to: AuthState, why: &str) {
+    log!(INFO, "[{}] Auth: {} -> {} ({})", portal(state), flow.state, to, why);
+    flow.state = to;
+    flow.attempts = 0;
+    flow.since = Some(Instant::now());
+}
+
+/// Logged in: frees the auth slot, reports the bot online and moves on to the portal.
+fn authenticated(bot: &Client, state: &State, flow: &mut AuthFlow, why: &str) {
+    transition(state, flow, AuthState::Authenticated, why);
+    flow.since = None;
+    state.auth_slot.release();
+    state.signals.send(BotSignal::Online);
+    navigation::on_authenticated(bot, state);
+}
+
+fn failed(bot: &Client, state: &State, flow: &mut AuthFlow, why: &str) {
+    transition(state, flow, AuthState::Failed, why);
+    flow.since = None;
+    state.auth_slot.release();
+    state.signals.send(BotSignal::Disconnected(format!("auth failed: {why}")));
+    bot.disconnect();
+}
+
+fn portal(state: &State) -> String {
+    state.config.read().bot.id.clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::tests::{line, GLOBAL};
+
+    fn flow(state: AuthState) -> AuthFlow {
+        AuthFlow { state, ..Default::default() }
+    }
+
+    #[test]
+    fn prompts_pick_the_command() {
+        let patterns = PatternCatalog::with(&[]);
+        let awaiting = flow(AuthState::AwaitingPrompt);
+        assert_eq!(awaiting.step(&patterns, "Please /register <password>"), Some(Step::Send(AuthState::Registering, "register prompt")));
+        assert_eq!(awaiting.step(&patterns, "Please /login <password>"), Some(Step::Send(AuthState::LoggingIn, "login prompt")));
+        assert_eq!(flow(AuthState::Registering).step(&patterns, "You are already registered"),
+            Some(Step::Send(AuthState::LoggingIn, "already registered")));
+        assert_eq!(flow(AuthState::LoggingIn).step(&patterns, "Wrong password!"), Some(Step::WrongPassword));
+        assert_eq!(flow(AuthState::Idle).step(&patterns, "Please /login <password>"), None);
+    }
+
+    #[test]
+    fn player_lines_leave_the_flow_alone() {
+        let patterns = PatternCatalog::with(&[(Pattern::ChatGlobal, GLOBAL)]);
+        let flow = flow(AuthState::LoggingIn);
+
+        let global = line("[G] Griefer: Вы успешно авторизовались", &patterns);
+        assert_eq!(global.server_text().and_then(|text| flow.step(&patterns, text)), None);
+        assert_eq!(flow.state(), AuthState::LoggingIn);
+
+        let system = line("Вы успешно авторизовались", &patterns);
+        assert_eq!(system.server_text().and_then(|text| flow.step(&patterns, text)), Some(Step::Authenticated("logged in")));
+    }
+}
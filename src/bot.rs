@@ -0,0 +1,116 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use anyhow::{anyhow, Result};
+use azalea::{prelude::*, protocol::connect::Proxy, JoinOpts, ServerAddress};
+use parking_lot::{Mutex, RwLock};
+use sysx::io::log::*;
+
+use crate::{
+    config::{LoadedConfig, RuntimeConfig},
+    connect::{self, DnsResolver},
+    schedule::AuthSlot,
+    store::PortalStore,
+    supervisor::Signals,
+    types::{Session, State},
+};
+
+/// Server version used when `server.version` is not set, no ViaVersion plugin is loaded for it.
+pub const AUTO_VERSION: &str = "AUTO";
+
+/// Where and how one portal bot joins.
+pub struct JoinTarget {
+    pub account: Account,
+    /// `host:port` sent in the handshake.
+    pub address: String,
+    pub options: JoinOpts,
+    /// How long the join may take, `server.timeout`.
+    pub timeout: Duration,
+}
+
+impl JoinTarget {
+    /// Join options with the server address baked in, as the swarm needs them.
+    pub fn swarm_options(&self) -> Result<JoinOpts> {
+        let address = ServerAddress::try_from(self.address.as_str())
+            .map_err(|err| anyhow!("Invalid server address {}: {}", self.address, err))?;
+        Ok(self.options.clone().custom_address(address))
+    }
+}
+
+/// Resolves the proxy and the server addresses to join the portal through, best first.
+/// Without a proxy that is the first reachable address; behind one every address is
+/// returned, to be tried in turn until a join succeeds.
+pub async fn join_targets(loaded: &LoadedConfig) -> Result<Vec<JoinTarget>> {
+    let (server_config, proxy_config) = (&loaded.server, &loaded.proxy);
+
+    let options = if let (Some(proxy_host), Some(proxy_port)) =
+        (proxy_config.host.as_deref(), proxy_config.port)
+    {
+        let proxy_addr = tokio::net::lookup_host(format!("{proxy_host}:{proxy_port}"))
+            .await?
+            .find(|addr| addr.is_ipv4())
+            .ok_or_else(|| anyhow!("Could not resolve proxy host to an IPv4 address: {}", proxy_host))?;
+
+        let proxy_socket_addr = match proxy_addr {
+             SocketAddr::V4(addr) => addr,
+             SocketAddr::V6(_) => return Err(anyhow!("IPv6 proxies are not supported yet")),
+        };
+
+        let proxy = Proxy::new(SocketAddr::V4(proxy_socket_addr), None);
+        JoinOpts::new().proxy(proxy)
+    } else {
+        JoinOpts::new()
+    };
+
+    let name_server = server_config
+        .dns
+        .as_deref()
+        .map(connect::parse_name_server)
+        .transpose()
+        .map_err(|err| anyhow!("server.dns: {}", err))?;
+    let resolver = DnsResolver::new(name_server)?;
+    let behind_proxy = proxy_config.host.is_some();
+    let resolved = connect::resolve_server(server_config, &resolver, behind_proxy, &loaded.runtime.bot.id).await?;
+    let timeout = connect::server_timeout(server_config)?;
+
+    Ok(resolved
+        .into_iter()
+        .map(|resolved| JoinTarget {
+            account: Account::offline(&loaded.runtime.bot.nickname),
+            address: resolved.address,
+            options: options.clone().custom_resolved_address(resolved.socket),
+            timeout,
+        })
+        .collect())
+}
+
+/// The server version the portal needs, `AUTO` when unset.
+pub fn server_version(loaded: &LoadedConfig) -> String {
+    loaded.server.version.clone().unwrap_or_else(|| AUTO_VERSION.to_string())
+}
+
+pub fn initial_state(
+    config: Arc<RwLock<RuntimeConfig>>,
+    client: Arc<Mutex<Option<Client>>>,
+    auth_slot: AuthSlot,
+    signals: Signals,
+    store: Option<PortalStore>,
+) -> State {
+    let session = match &store {
+        Some(store) => Session::restore(store).unwrap_or_else(|err| {
+            log!(INFO, "[{}] Failed to restore counters and cooldowns: {:#}", config.read().bot.id, err);
+            Session::default()
+        }),
+        None => Session::default(),
+    };
+    State {
+        config,
+        client,
+        auth_slot,
+        auth: Arc::default(),
+        nav: Arc::default(),
+        outbox: Arc::default(),
+        signals,
+        store,
+        session: Arc::new(RwLock::new(session)),
+    }
+}
@@ -0,0 +1,222 @@
+use std::fmt;
+
+use azalea::{chat::ChatPacket, FormattedText};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{Pattern, PatternCatalog};
+
+/// Where a chat line was said.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatChannel {
+    Global,
+    Local,
+    Clan,
+    Personal,
+    /// A server message known to one of the other patterns, e.g. an auth prompt.
+    System,
+    /// Matches no format, see the `chat.*` patterns.
+    Unknown,
+}
+
+impl ChatChannel {
+    /// The channels with a line format, in the order they are tried.
+    const FORMATS: [(ChatChannel, Pattern); 4] = [
+        (ChatChannel::Personal, Pattern::ChatPersonal),
+        (ChatChannel::Clan, Pattern::ChatClan),
+        (ChatChannel::Local, Pattern::ChatLocal),
+        (ChatChannel::Global, Pattern::ChatGlobal),
+    ];
+}
+
+impl fmt::Display for ChatChannel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ChatChannel::Global => "global",
+            ChatChannel::Local => "local",
+            ChatChannel::Clan => "clan",
+            ChatChannel::Personal => "personal",
+            ChatChannel::System => "system",
+            ChatChannel::Unknown => "unknown",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A chat line split into its parts by the server's line formats.
+#[derive(Debug, Clone)]
+pub struct ChatMessage {
+    pub channel: ChatChannel,
+    pub sender: Option<String>,
+    /// Rank or prefix in front of the nickname, e.g. `Admin`.
+    pub rank: Option<String>,
+    pub clan: Option<String>,
+    /// Who a personal message was sent to.
+    pub recipient: Option<String>,
+    /// What was said, without the channel, rank and nickname. The whole line when unparsed.
+    pub body: String,
+    /// The whole line as plain text.
+    pub text: String,
+    pub formatted: FormattedText,
+}
+
+impl ChatMessage {
+    /// Parses `packet`, received by the bot `nickname`, with the `chat.*` formats. Each
+    /// format needs `sender` and `message` groups and may have `rank`, `clan` and
+    /// `recipient` ones.
+    pub fn parse(packet: &ChatPacket, nickname: &str, patterns: &PatternCatalog) -> Self {
+        let signed = match packet.split_sender_and_content() {
+            // A whisper only ever reaches the player it was sent to
+            (Some(sender), body) => Some((sender, body, packet.is_whisper().then(|| nickname.to_string()))),
+            (None, _) => None,
+        };
+        Self::parse_text(packet.content(), packet.message(), signed, patterns)
+    }
+
+    /// Parses the plain `text` of a line. `signed` is the sender, body and, for a whisper,
+    /// recipient of signed player chat, used when no format matches.
+    fn parse_text(
+        text: String,
+        formatted: FormattedText,
+        signed: Option<(String, String, Option<String>)>,
+        patterns: &PatternCatalog,
+    ) -> Self {
+        let mut message = Self {
+            channel: ChatChannel::Unknown,
+            sender: None,
+            rank: None,
+            clan: None,
+            recipient: None,
+            body: text.clone(),
+            text: text.clone(),
+            formatted,
+        };
+
+        for (channel, pattern) in ChatChannel::FORMATS {
+            let Some(captures) = patterns.captures(pattern, &text) else { continue };
+            let group = |name| captures.name(name).map(|group| group.as_str().trim().to_string());
+            message.channel = channel;
+            message.sender = group("sender");
+            message.rank = group("rank").filter(|rank| !rank.is_empty());
+            message.clan = group("clan").filter(|clan| !clan.is_empty());
+            message.recipient = group("recipient");
+            message.body = group("message").unwrap_or_default();
+            return message;
+        }
+
+        // Signed player chat carries its sender, which covers servers with vanilla chat
+        if let Some((sender, body, recipient)) = signed {
+            message.channel = if recipient.is_some() { ChatChannel::Personal } else { ChatChannel::Global };
+            message.sender = Some(sender);
+            message.recipient = recipient;
+            message.body = body;
+        } else if Pattern::ALL.into_iter().any(|pattern| patterns.is_match(pattern, &text)) {
+            message.channel = ChatChannel::System;
+        }
+        message
+    }
+
+    /// The text of a server message, `None` for player chat and for lines no pattern knows.
+    /// Only these lines may drive the login, navigation and outbox flows, or a player could
+    /// type a server prompt. Telling them apart from unsigned player chat takes the `chat.*`
+    /// formats of the server.
+    pub fn server_text(&self) -> Option<&str> {
+        (self.channel == ChatChannel::System).then_some(self.text.as_str())
+    }
+
+    /// Whether `nickname` sent it, ignoring case like the server does.
+    pub fn is_from(&self, nickname: &str) -> bool {
+        self.sender.as_deref().is_some_and(|sender| sender.eq_ignore_ascii_case(nickname))
+    }
+
+    /// Whether it is a personal message to `nickname`. Only a signed whisper or a parsed
+    /// `recipient` tells, so the `chat.personal` format of the server needs that group.
+    pub fn is_to(&self, nickname: &str) -> bool {
+        self.recipient.as_deref().is_some_and(|recipient| recipient.eq_ignore_ascii_case(nickname))
+    }
+}
+
+impl fmt::Display for ChatMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.sender {
+            Some(sender) => write!(f, "[{}] {}: {}", self.channel, sender, self.body),
+            None => write!(f, "[{}] {}", self.channel, self.body),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) const GLOBAL: &str = r"^\[G\] (?:\[(?P<rank>[^\]]+)\] )?(?P<sender>\w{3,16}): (?P<message>.*)$";
+    const PERSONAL: &str = r"^\[(?P<sender>\w{3,16}) -> (?P<recipient>\w{3,16})\] (?P<message>.*)$";
+
+    pub(crate) fn line(text: &str, patterns: &PatternCatalog) -> ChatMessage {
+        parse(text, None, patterns)
+    }
+
+    fn parse(text: &str, signed: Option<(&str, &str, Option<&str>)>, patterns: &PatternCatalog) -> ChatMessage {
+        let signed =
+            signed.map(|(sender, body, recipient)| (sender.to_string(), body.to_string(), recipient.map(str::to_string)));
+        ChatMessage::parse_text(text.to_string(), FormattedText::default(), signed, patterns)
+    }
+
+    fn patterns() -> PatternCatalog {
+        PatternCatalog::with(&[(Pattern::ChatGlobal, GLOBAL), (Pattern::ChatPersonal, PERSONAL)])
+    }
+
+    #[test]
+    fn formats_split_lines_into_their_parts() {
+        let patterns = patterns();
+        let global = line("[G] [Admin] Steve: hello there", &patterns);
+        assert_eq!(global.channel, ChatChannel::Global);
+        assert_eq!((global.rank.as_deref(), global.sender.as_deref()), (Some("Admin"), Some("Steve")));
+        assert_eq!(global.body, "hello there");
+        assert_eq!(global.text, "[G] [Admin] Steve: hello there");
+
+        let personal = line("[Steve -> Kemper1] !status", &patterns);
+        assert_eq!(personal.channel, ChatChannel::Personal);
+        assert_eq!(personal.recipient.as_deref(), Some("Kemper1"));
+        assert_eq!(personal.body, "!status");
+        assert!(personal.is_from("steve"));
+        assert!(personal.is_to("kemper1"));
+
+        let spied = line("[Steve -> Alex] !status", &patterns);
+        assert!(!spied.is_to("Kemper1"));
+        assert!(!global.is_to("Kemper1"));
+    }
+
+    #[test]
+    fn lines_without_a_format_are_system_or_unknown() {
+        let patterns = patterns();
+        let system = line("Please /login <password>", &patterns);
+        assert_eq!((system.channel, system.sender.as_deref()), (ChatChannel::System, None));
+        assert_eq!(system.server_text(), Some("Please /login <password>"));
+
+        let unknown = line("Welcome to the server", &patterns);
+        assert_eq!(unknown.channel, ChatChannel::Unknown);
+        assert_eq!(unknown.body, "Welcome to the server");
+        assert_eq!(unknown.server_text(), None);
+    }
+
+    #[test]
+    fn signed_chat_is_used_when_no_format_matches() {
+        let patterns = PatternCatalog::with(&[]);
+        let global = parse("<Steve> hi", Some(("Steve", "hi", None)), &patterns);
+        assert_eq!((global.channel, global.sender.as_deref(), global.body.as_str()), (ChatChannel::Global, Some("Steve"), "hi"));
+        assert_eq!(global.server_text(), None);
+        assert!(!global.is_to("Kemper1"));
+    }
+
+    #[test]
+    fn signed_whispers_are_to_the_bot() {
+        let patterns = PatternCatalog::with(&[]);
+        let whisper = parse("Steve whispers to you: !status", Some(("Steve", "!status", Some("Kemper1"))), &patterns);
+        assert_eq!(whisper.channel, ChatChannel::Personal);
+        assert_eq!(whisper.body, "!status");
+        assert!(whisper.is_from("steve"));
+        assert!(whisper.is_to("kemper1"));
+    }
+}
@@ -0,0 +1,345 @@
+use azalea::prelude::*;
+use sysx::io::log::*;
+use tokio::time::Instant;
+
+use crate::{
+    chat::ChatMessage,
+    config::CommandsResolved,
+    navigation,
+    outbox::{self, Outgoing, Priority},
+    store::AuditEntry,
+    supervisor::BotSignal,
+    types::State,
+};
+
+/// A command the bot accepts by private message.
+pub struct Command {
+    pub name: &'static str,
+    /// Arguments as shown by `help`, e.g. `<text>`.
+    pub usage: &'static str,
+    pub about: &'static str,
+    pub min_args: usize,
+    /// `None` when the last argument takes the rest of the message.
+    pub max_args: Option<usize>,
+    run: fn(&Context, &[String]) -> Result<String, String>,
+}
+
+/// Who ran a command and where.
+pub struct Context<'a> {
+    pub bot: &'a Client,
+    pub state: &'a State,
+    pub sender: &'a str,
+    pub grants: &'a Grants,
+}
+
+pub const COMMANDS: &[Command] = &[
+    Command { name: "help", usage: "[command]", about: "lists the commands or explains one", min_args: 0, max_args: Some(1), run: help },
+    Command { name: "say", usage: "<text>", about: "says the text in chat", min_args: 1, max_args: None, run: say },
+    Command { name: "warp", usage: "", about: "warps to the portal's warp again", min_args: 0, max_args: Some(0), run: warp },
+    Command { name: "portal", usage: "", about: "enters the portal again", min_args: 0, max_args: Some(0), run: portal },
+    Command { name: "status", usage: "", about: "tells what the bot is doing", min_args: 0, max_args: Some(0), run: status },
+    Command { name: "pause", usage: "", about: "stops reactions and warp enforcement", min_args: 0, max_args: Some(0), run: pause },
+    Command { name: "resume", usage: "", about: "undoes pause", min_args: 0, max_args: Some(0), run: resume },
+    Command { name: "reconnect", usage: "", about: "reconnects the bot", min_args: 0, max_args: Some(0), run: reconnect },
+];
+
+pub fn find(name: &str) -> Option<&'static Command> {
+    COMMANDS.iter().find(|command| command.name.eq_ignore_ascii_case(name))
+}
+
+/// Roles of a player: `owner` from `commands.owners`, then the roles listing their
+/// nickname or, when the tab list knows it, their UUID.
+pub struct Grants {
+    pub uuid: Option<String>,
+    pub roles: Vec<String>,
+}
+
+impl Grants {
+    pub fn of(bot: &Client, commands: &CommandsResolved, player: &str) -> Self {
+        let uuid = bot
+            .tab_list()
+            .values()
+            .find(|info| info.profile.name.eq_ignore_ascii_case(player))
+            .map(|info| info.uuid.to_string().to_lowercase());
+        let mut roles = Vec::new();
+        if commands.owners.iter().any(|owner| owner.eq_ignore_ascii_case(player)) {
+            roles.push(OWNER_ROLE.to_string());
+        }
+        for (name, role) in &commands.roles {
+            let by_name = role.players.iter().any(|listed| listed.eq_ignore_ascii_case(player));
+            let by_uuid = uuid.as_ref().is_some_and(|uuid| role.uuids.contains(uuid));
+            if by_name || by_uuid {
+                roles.push(name.clone());
+            }
+        }
+        Self { uuid, roles }
+    }
+
+    /// The first role allowed to run `command`.
+    pub fn allows(&self, commands: &CommandsResolved, command: &str) -> Option<&str> {
+        self.roles.iter().map(String::as_str).find(|name| {
+            *name == OWNER_ROLE || commands.roles.get(*name).is_some_and(|role| role.grants(command))
+        })
+    }
+}
+
+/// Role of the players in `commands.owners`, allowed every command.
+pub const OWNER_ROLE: &str = "owner";
+
+/// Runs a command sent by private message to the bot if the sender's roles allow it,
+/// answering by private message either way, and records the attempt in the audit trail.
+/// Returns whether the message was a command, so that it is not handled any further.
+pub fn on_message(bot: &Client, state: &State, message: &ChatMessage) -> bool {
+    let Some(sender) = message.sender.as_deref() else { return false };
+    let (portal, nickname, commands) = {
+        let config = state.config.read();
+        (config.bot.id.clone(), config.bot.nickname.clone(), config.commands.clone())
+    };
+    // Messages between other players, e.g. shown by a social spy, are never commands
+    if !message.is_to(&nickname) {
+        return false;
+    }
+    let Some(line) = message.body.trim().strip_prefix(commands.prefix.as_str()) else { return false };
+    // Only the parsed sender counts, whatever the message itself says
+    let grants = Grants::of(bot, &commands, sender);
+    if grants.roles.is_empty() {
+        // Without a prefix any private message would be a denied command
+        if commands.prefix.is_empty() {
+            return false;
+        }
+        let name = line.split_whitespace().next().unwrap_or_default();
+        deny(bot, state, &grants, sender, name, line, "no role");
+        return true;
+    }
+
+    let args = match split_args(line) {
+        Ok(args) if !args.is_empty() => args,
+        Ok(_) => return false,
+        Err(err) => {
+            reply(bot, state, sender, &err);
+            return true;
+        }
+    };
+    let Some(command) = find(&args[0]) else {
+        reply(bot, state, sender, &format!("Unknown command {}, see {}help", args[0], commands.prefix));
+        return true;
+    };
+    let args = join_rest(command, &args[1..]);
+    let Some(role) = grants.allows(&commands, command.name).map(str::to_string) else {
+        deny(bot, state, &grants, sender, command.name, &args.join(" "), "not granted");
+        return true;
+    };
+    if args.len() < command.min_args || command.max_args.is_some_and(|max| args.len() > max) {
+        reply(bot, state, sender, &format!("Usage: {}{} {}", commands.prefix, command.name, command.usage));
+        return true;
+    }
+
+    let cooldown = commands.cooldowns.get(command.name).copied().unwrap_or(commands.cooldown);
+    if let Some(left) = state.try_use(&format!("command:{}", command.name), cooldown) {
+        reply(bot, state, sender, &format!("{} is cooling down, try again in {}s", command.name, left.as_secs() + 1));
+        return true;
+    }
+
+    log!(INFO, "[{}] Command from {} ({}): {} {}", portal, sender, role, command.name, args.join(" "));
+    audit(state, AuditEntry {
+        player: sender.to_string(),
+        uuid: grants.uuid.clone(),
+        command: command.name.to_string(),
+        args: args.join(" "),
+        allowed: true,
+        reason: role,
+    });
+    let context = Context { bot, state, sender, grants: &grants };
+    match (command.run)(&context, &args) {
+        Ok(answer) | Err(answer) => reply(bot, state, sender, &answer),
+    }
+    true
+}
+
+/// Refuses a command. The answer is sent at most once per `commands.cooldown` to each
+/// player so that denials cannot be used to flood the chat; every attempt is audited.
+fn deny(bot: &Client, state: &State, grants: &Grants, sender: &str, command: &str, args: &str, why: &str) {
+    let (portal, cooldown) = {
+        let config = state.config.read();
+        (config.bot.id.clone(), config.commands.cooldown)
+    };
+    log!(INFO, "[{}] Denied command from {}: {} {} ({})", portal, sender, command, args, why);
+    audit(state, AuditEntry {
+        player: sender.to_string(),
+        uuid: grants.uuid.clone(),
+        command: command.to_string(),
+        args: args.to_string(),
+        allowed: false,
+        reason: why.to_string(),
+    });
+
+    let answer = state.update(|session| {
+        let now = Instant::now();
+        let player = sender.to_lowercase();
+        if session.denials.get(&player).is_some_and(|last| now.duration_since(*last) < cooldown) {
+            return false;
+        }
+        session.denials.insert(player, now);
+        true
+    });
+    if answer {
+        reply(bot, state, sender, &format!("You may not use {command}"));
+    }
+}
+
+fn audit(state: &State, entry: AuditEntry) {
+    let Some(store) = &state.store else { return };
+    if let Err(err) = store.audit(&entry) {
+        log!(INFO, "[{}] Failed to audit a command: {:#}", state.config.read().bot.id, err);
+    }
+}
+
+/// Splits a command line on whitespace, keeping double-quoted parts together.
+pub fn split_args(line: &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut started = false;
+    for ch in line.chars() {
+        match ch {
+            '"' => {
+                quoted = !quoted;
+                started = true;
+            }
+            ch if ch.is_whitespace() && !quoted => {
+                if started {
+                    args.push(std::mem::take(&mut current));
+                    started = false;
+                }
+            }
+            ch => {
+                current.push(ch);
+                started = true;
+            }
+        }
+    }
+    if quoted {
+        return Err("Unclosed quote".to_string());
+    }
+    if started {
+        args.push(current);
+    }
+    Ok(args)
+}
+
+/// Commands without a maximum take the rest of the line as their last argument.
+fn join_rest(command: &Command, args: &[String]) -> Vec<String> {
+    match command.max_args {
+        None if args.len() > command.min_args.max(1) => {
+            let split = command.min_args.max(1) - 1;
+            let mut joined = args[..split].to_vec();
+            joined.push(args[split..].join(" "));
+            joined
+        }
+        _ => args.to_vec(),
+    }
+}
+
+fn reply(bot: &Client, state: &State, sender: &str, text: &str) {
+    outbox::send(bot, state, Priority::Replies, Outgoing::Chat(format!("/msg {sender} {text}")));
+}
+
+fn help(context: &Context, args: &[String]) -> Result<String, String> {
+    let commands = context.state.config.read().commands.clone();
+    let prefix = &commands.prefix;
+    match args.first() {
+        Some(name) => {
+            let command = find(name).ok_or_else(|| format!("Unknown command {name}"))?;
+            Ok(format!("{prefix}{} {} - {}", command.name, command.usage, command.about))
+        }
+        None => {
+            let names: Vec<_> = COMMANDS
+                .iter()
+                .filter(|command| context.grants.allows(&commands, command.name).is_some())
+                .map(|command| command.name)
+                .collect();
+            Ok(format!("Commands: {}. {prefix}help <command> explains one", names.join(", ")))
+        }
+    }
+}
+
+fn say(context: &Context, args: &[String]) -> Result<String, String> {
+    outbox::send(context.bot, context.state, Priority::Replies, Outgoing::Chat(args[0].clone()));
+    Ok("Said".to_string())
+}
+
+fn warp(context: &Context, _args: &[String]) -> Result<String, String> {
+    let why = format!("requested by {}", context.sender);
+    if navigation::rewarp(context.bot, context.state, &why) {
+        Ok("Warping".to_string())
+    } else {
+        Err("Not on the portal yet".to_string())
+    }
+}
+
+fn portal(context: &Context, _args: &[String]) -> Result<String, String> {
+    let why = format!("requested by {}", context.sender);
+    if navigation::reenter_portal(context.bot, context.state, &why) {
+        Ok("Entering the portal".to_string())
+    } else {
+        Err("Not logged in yet".to_string())
+    }
+}
+
+fn status(context: &Context, _args: &[String]) -> Result<String, String> {
+    let snapshot = context.state.snapshot();
+    let session = &snapshot.session;
+    Ok(format!(
+        "{}: auth {}, {}{}, {} session(s), {} spawn(s), {} chat line(s) so far",
+        snapshot.portal,
+        snapshot.auth,
+        snapshot.stage,
+        if session.flags.paused { ", paused" } else { "" },
+        session.counters.sessions,
+        session.counters.spawn,
+        session.counters.chat,
+    ))
+}
+
+fn pause(context: &Context, _args: &[String]) -> Result<String, String> {
+    let was_paused = context.state.update(|session| std::mem::replace(&mut session.flags.paused, true));
+    if was_paused { Err("Already paused".to_string()) } else { Ok("Paused".to_string()) }
+}
+
+fn resume(context: &Context, _args: &[String]) -> Result<String, String> {
+    let was_paused = context.state.update(|session| std::mem::replace(&mut session.flags.paused, false));
+    if was_paused { Ok("Resumed".to_string()) } else { Err("Not paused".to_string()) }
+}
+
+fn reconnect(context: &Context, _args: &[String]) -> Result<String, String> {
+    context.state.signals.send(BotSignal::Reconnect(format!("requested by {}", context.sender)));
+    Ok("Reconnecting".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        split_args(line).unwrap()
+    }
+
+    #[test]
+    fn split_args_keeps_quoted_parts_together() {
+        assert_eq!(args("  say   hello  world "), ["say", "hello", "world"]);
+        assert_eq!(args(r#"say "hello  world" "" x"#), ["say", "hello  world", "", "x"]);
+        assert!(args("   ").is_empty());
+        assert_eq!(split_args(r#"say "unclosed"#), Err("Unclosed quote".to_string()));
+    }
+
+    #[test]
+    fn join_rest_fills_the_last_argument() {
+        let say = find("say").unwrap();
+        assert_eq!(join_rest(say, &args("hello big world")), ["hello big world"]);
+        assert_eq!(join_rest(say, &args("hi")), ["hi"]);
+
+        // Commands with a maximum keep their arguments apart, for the usage check
+        let help = find("HELP").unwrap();
+        assert_eq!(join_rest(help, &args("say more")), ["say", "more"]);
+    }
+}
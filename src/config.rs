@@ -1,80 +1,421 @@
-use std::path::Path;
+mod duration;
+mod kick;
+mod merge;
+mod patterns;
+mod portals;
+mod provenance;
+mod reactions;
+mod secrets;
+mod validate;
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 use anyhow::{anyhow, Context, Result};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use sysx::io::log::*;
+
+pub use duration::{format_duration, parse_duration, ConfigDuration};
+pub use kick::{KickAction, KickCategory, KickClassifier, KickVerdict};
+pub use merge::Merge;
+pub use patterns::{load_patterns, Pattern, PatternCatalog, PatternIssue, PATTERNS_FILE};
+pub use portals::parse_portal_set;
+pub use provenance::{explain, Provenance, Source};
+pub use reactions::{Fired, ReactionAction, Reactions};
+pub use secrets::{seal_secrets, PasswordSource, DEFAULT_PASSPHRASE_ENV};
+pub use validate::{check_tree, ConfigErrors, ConfigIssue};
+use merge::config_sections;
+
+const DEFAULT_JOIN_GAP: Duration = Duration::from_secs(5);
+const DEFAULT_PROXY_GAP: Duration = Duration::from_secs(2);
+const DEFAULT_MAX_AUTHENTICATING: u32 = 2;
+const DEFAULT_AUTH_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_AUTH_STEP_TIMEOUT: Duration = Duration::from_secs(15);
+const DEFAULT_AUTH_ATTEMPTS: u32 = 3;
+const DEFAULT_NAVIGATION_TIMEOUT: Duration = Duration::from_secs(20);
+const DEFAULT_NAVIGATION_ATTEMPTS: u32 = 3;
+const DEFAULT_WARP_DISTANCE: f64 = 5.0;
+const DEFAULT_CONFIRM_DISTANCE: f64 = 3.0;
+const DEFAULT_WARP_COOLDOWN: Duration = Duration::from_secs(30);
+const DEFAULT_COMMAND_PREFIX: &str = "!";
+const DEFAULT_COMMAND_COOLDOWN: Duration = Duration::from_secs(3);
+const DEFAULT_OUTBOX_INTERVAL: Duration = Duration::from_secs(1);
+const DEFAULT_OUTBOX_BURST: u32 = 3;
+const DEFAULT_OUTBOX_REFILL: Duration = Duration::from_secs(3);
+const DEFAULT_OUTBOX_MAX_SLOWDOWN: f64 = 8.0;
+const DEFAULT_OUTBOX_RECOVER_AFTER: Duration = Duration::from_secs(30);
+const DEFAULT_OUTBOX_MAX_QUEUED: u32 = 50;
+const DEFAULT_RESTART_DELAY: Duration = Duration::from_secs(5);
+const DEFAULT_RESTART_MAX_DELAY: Duration = Duration::from_secs(5 * 60);
+const DEFAULT_RESTART_JITTER: f64 = 0.2;
+const DEFAULT_MAX_RETRIES: u32 = 10;
+const DEFAULT_PARK_FOR: Duration = Duration::from_secs(30 * 60);
+const DEFAULT_STABLE_AFTER: Duration = Duration::from_secs(60);
+
+config_sections! {
+    pub struct Config {
+        /// Files this one inherits from, relative to it. They are applied before the
+        /// file itself, in the listed order, so the merged value carries no meaning.
+        pub extends: Option<Vec<String>>,
+        /// Portals of a server that need no file of their own, e.g. `"s1..s10"`.
+        /// Usually combined with a templated `bot.nickname = "Kemper{n}"`, see
+        /// `Config::apply_portal_template`.
+        pub portals: Option<String>,
+        pub bot: Option<BotConfig>,
+        pub server: Option<ServerConfig>,
+        pub proxy: Option<ProxyConfig>,
+        pub delay: Option<DelayConfig>,
+        pub join: Option<JoinConfig>,
+        pub auth: Option<AuthConfig>,
+        pub navigation: Option<NavigationConfig>,
+        pub restart: Option<RestartConfig>,
+        pub kick: Option<KickConfig>,
+        pub chat: Option<ChatConfig>,
+        pub commands: Option<CommandsConfig>,
+        pub outbox: Option<OutboxConfig>,
+        pub secrets: Option<SecretsConfig>,
+    }
+
+    pub struct SecretsConfig {
+        /// Encrypted secrets file, relative to the config file that sets it.
+        pub file: Option<String>,
+        /// Environment variable holding the passphrase, `MRSBOT_SECRETS_PASSPHRASE` by default.
+        pub passphrase_env: Option<String>,
+    }
+
+    pub struct ServerConfig {
+        /// Primary address. Without `port` the `_minecraft._tcp` SRV record is used.
+        pub host: Option<String>,
+        pub port: Option<u16>,
+        /// Fallback `host[:port]` addresses, tried in order when the primary one is down.
+        pub addresses: Option<Vec<String>>,
+        /// How long each address gets to accept a connection and the join, 5 seconds by default.
+        pub timeout: Option<ConfigDuration>,
+        /// Name server (`ip[:port]`) for SRV and host lookups instead of the system one.
+        pub dns: Option<String>,
+        pub version: Option<String>,
+    }
+
+    pub struct ProxyConfig {
+        pub host: Option<String>,
+        pub port: Option<u16>,
+    }
+
+    /// Pacing of connections when several portals start at once.
+    pub struct JoinConfig {
+        /// Minimum time between two connections to the same server host, 5 seconds by default.
+        pub gap: Option<ConfigDuration>,
+        /// Minimum time between two connections through the same proxy, 2 seconds by default.
+        pub proxy_gap: Option<ConfigDuration>,
+        /// How many bots of a server may be logging in at the same time, 2 by default.
+        pub max_authenticating: Option<u32>,
+        /// A bot still logging in after this long frees its auth slot anyway, 30 seconds by default.
+        pub auth_timeout: Option<ConfigDuration>,
+    }
+
+    /// The `/reg` / `/login` flow after joining.
+    pub struct AuthConfig {
+        /// How long to wait for a prompt or an answer before sending the command again, 15 seconds by default.
+        pub timeout: Option<ConfigDuration>,
+        /// Commands sent per step before giving up and disconnecting, 3 by default.
+        pub max_attempts: Option<u32>,
+    }
+
+    /// Getting from the lobby to the portal and the warp after logging in.
+    pub struct NavigationConfig {
+        /// How long to wait for the portal or warp to be reached before sending the command again, 20 seconds by default.
+        pub timeout: Option<ConfigDuration>,
+        /// Commands sent per stage before giving up and disconnecting, 3 by default.
+        pub max_attempts: Option<u32>,
+        /// How far the server has to move the bot for the portal or the first warp to count as
+        /// reached, and how close to the warp a warp back has to put it, 3 blocks by default.
+        /// At most `warp_distance`.
+        pub confirm_distance: Option<f64>,
+        /// How far from the warp the bot may be moved before it warps back, 5 blocks by default.
+        pub warp_distance: Option<f64>,
+        /// Minimum time between two warps, 30 seconds by default.
+        pub warp_cooldown: Option<ConfigDuration>,
+    }
+
+    /// Restarting a bot after it failed to join or was disconnected. Consecutive
+    /// failures back off exponentially; after `max_retries` of them the portal is parked.
+    pub struct RestartConfig {
+        /// Delay after the first failure, 5 seconds by default. Doubles with every further one.
+        pub initial_delay: Option<ConfigDuration>,
+        /// Upper bound of the delay, 5 minutes by default.
+        pub max_delay: Option<ConfigDuration>,
+        /// Random spread of each delay as a fraction of it, 0.2 (±20%) by default.
+        pub jitter: Option<f64>,
+        /// Consecutive failures before the portal is parked, 10 by default.
+        pub max_retries: Option<u32>,
+        /// How long a parked portal waits before a single trial join, 30 minutes by default.
+        pub park_for: Option<ConfigDuration>,
+        /// A session online for this long counts as a success and resets the backoff, 1 minute by default.
+        pub stable_after: Option<ConfigDuration>,
+    }
+
+    /// Classification of disconnect reasons, usually set per server in all.toml.
+    pub struct KickConfig {
+        /// Delay of the `delay` action for rules that set none, 30 seconds by default.
+        pub delay: Option<ConfigDuration>,
+        /// Checked in order, the first rule whose pattern matches the reason wins.
+        /// Reasons no rule matches are `unknown` and handled with the usual backoff.
+        pub rules: Option<Vec<KickRule>>,
+    }
+
+    pub struct KickRule {
+        /// Regex matched against the plain text of the disconnect reason.
+        pub pattern: Option<String>,
+        pub category: Option<KickCategory>,
+        /// Overrides the default action of the category.
+        pub action: Option<KickAction>,
+        /// Delay of the `delay` action.
+        pub delay: Option<ConfigDuration>,
+    }
+
+    /// Reactions to chat lines, run by the handler of the line's channel.
+    pub struct ChatConfig {
+        /// Checked in order, every matching reaction fires.
+        pub reactions: Option<Vec<ChatReaction>>,
+    }
+
+    pub struct ChatReaction {
+        /// `global`, `local`, `clan`, `personal`, `system` or `unknown`; every channel when unset.
+        pub channel: Option<crate::chat::ChatChannel>,
+        /// Regex matched against the message body.
+        pub pattern: Option<String>,
+        /// Regex matched against the sender's nickname.
+        pub sender: Option<String>,
+        pub action: Option<ReactionAction>,
+        /// Text of `say` and `reply`, `{sender}` and `{message}` are filled in.
+        pub text: Option<String>,
+        /// Minimum time between two firings of this reaction, none by default.
+        pub cooldown: Option<ConfigDuration>,
+    }
+
+    /// Commands sent to the bot by private message.
+    pub struct CommandsConfig {
+        /// What a message must start with to be a command, `!` by default. May be empty.
+        pub prefix: Option<String>,
+        /// Nicknames allowed to use every command, compared ignoring case.
+        pub owners: Option<Vec<String>>,
+        /// Roles by name, each granting some commands to some players.
+        pub roles: Option<BTreeMap<String, RoleConfig>>,
+        /// Minimum time between two uses of a command, 3 seconds by default. 0 turns it off.
+        pub cooldown: Option<ConfigDuration>,
+        /// Cooldowns of single commands, by command name.
+        pub cooldowns: Option<BTreeMap<String, ConfigDuration>>,
+    }
+
+    pub struct RoleConfig {
+        /// Commands the role may run, `*` for all of them.
+        pub commands: Option<Vec<String>>,
+        /// Nicknames with the role, compared ignoring case.
+        pub players: Option<Vec<String>>,
+        /// UUIDs with the role, looked up in the tab list so that renames keep it.
+        pub uuids: Option<Vec<String>>,
+    }
+
+    /// Pacing of everything the bot says, commands included, to stay under the server's flood limits.
+    pub struct OutboxConfig {
+        /// Minimum time between two messages, 1 second by default.
+        pub min_interval: Option<ConfigDuration>,
+        /// Messages that may go out back to back after a quiet period, 3 by default.
+        pub burst: Option<u32>,
+        /// Time to earn back one message of the burst, 3 seconds by default.
+        pub refill: Option<ConfigDuration>,
+        /// Upper bound of the slow-down after the server complains, 8 (times slower) by default.
+        pub max_slowdown: Option<f64>,
+        /// Time without complaints after which the slow-down is halved, 30 seconds by default.
+        pub recover_after: Option<ConfigDuration>,
+        /// Messages kept waiting at most, the least important are dropped first. 50 by default.
+        pub max_queued: Option<u32>,
+    }
+
+    pub struct DelayConfig {
+        pub min: Option<Delay>,
+        pub max: Option<Delay>,
+    }
 
-#[derive(Debug, Deserialize, Serialize, Default, Clone)]
-pub struct Config {
-    pub bot: Option<BotConfig>,
-    pub server: Option<ServerConfig>,
-    pub proxy: Option<ProxyConfig>,
-    pub delay: Option<DelayConfig>,
+    /// Delays in seconds, or strings such as `"150s"`, `"10m"` or `"1h30m"`.
+    pub struct Delay {
+        pub global: Option<ConfigDuration>,
+        pub discord: Option<ConfigDuration>,
+        pub invite: Option<ConfigDuration>,
+    }
 }
 
-#[derive(Debug, Deserialize, Serialize, Default, Clone)]
+/// The password is taken from exactly one of `password`, `password_env`,
+/// `password_file` or `password_secret`. A file that sets one of them replaces the
+/// source chosen by the files it overrides, so it is merged by hand.
+#[derive(Debug, Deserialize, Serialize, Default, Clone, PartialEq, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct BotConfig {
     pub nickname: Option<String>,
     pub password: Option<String>,
+    /// Name of an environment variable holding the password.
+    pub password_env: Option<String>,
+    /// File holding the password, relative to the config file that sets it.
+    pub password_file: Option<String>,
+    /// Name of the password in the encrypted `secrets.file`.
+    pub password_secret: Option<String>,
     pub warp: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize, Default, Clone)]
-pub struct ServerConfig {
-    pub host: Option<String>,
-    pub port: Option<u16>,
-    pub version: Option<String>,
-}
-
-#[derive(Debug, Deserialize, Serialize, Default, Clone)]
-pub struct ProxyConfig {
-    pub host: Option<String>,
-    pub port: Option<u16>,
-}
-
-#[derive(Debug, Deserialize, Serialize, Default, Clone)]
-pub struct DelayConfig {
-    pub min: Option<Delay>,
-    pub max: Option<Delay>,
-}
-
-#[derive(Debug, Deserialize, Serialize, Default, Clone)]
-pub struct Delay {
-    pub global: Option<i32>,
-    pub discord: Option<i32>,
-    pub invite: Option<i32>,
+impl Merge for BotConfig {
+    fn merge(&mut self, overlay: Self) {
+        // Destructured, so a new field does not compile until it is merged here too
+        let BotConfig { nickname, password, password_env, password_file, password_secret, warp } = overlay;
+        if password.is_some() || password_env.is_some() || password_file.is_some() || password_secret.is_some() {
+            self.password = None;
+            self.password_env = None;
+            self.password_file = None;
+            self.password_secret = None;
+        }
+        self.nickname.merge(nickname);
+        self.password.merge(password);
+        self.password_env.merge(password_env);
+        self.password_file.merge(password_file);
+        self.password_secret.merge(password_secret);
+        self.warp.merge(warp);
+    }
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize)]
 pub struct RuntimeConfig {
     pub bot: BotConfigResolved,
     pub delay: DelayResolved,
+    pub join: JoinResolved,
+    pub auth: AuthResolved,
+    pub navigation: NavigationResolved,
+    pub restart: RestartResolved,
+    pub commands: CommandsResolved,
+    pub outbox: OutboxResolved,
+    /// Chat patterns of the portal's server, see `patterns.toml`.
+    #[serde(skip)]
+    pub patterns: Arc<PatternCatalog>,
+    /// Compiled `chat.reactions`.
+    #[serde(skip)]
+    pub reactions: Arc<Reactions>,
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize)]
 pub struct BotConfigResolved {
     pub nickname: String,
     pub password: String,
+    pub password_source: PasswordSource,
     pub warp: String,
     pub portal: String,
+    /// `server/portal`, unique across a config tree unlike the portal name alone.
+    pub id: String,
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct JoinResolved {
+    #[serde(serialize_with = "duration::serialize_duration")]
+    pub gap: Duration,
+    #[serde(serialize_with = "duration::serialize_duration")]
+    pub proxy_gap: Duration,
+    pub max_authenticating: u32,
+    #[serde(serialize_with = "duration::serialize_duration")]
+    pub auth_timeout: Duration,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AuthResolved {
+    #[serde(serialize_with = "duration::serialize_duration")]
+    pub timeout: Duration,
+    pub max_attempts: u32,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct NavigationResolved {
+    #[serde(serialize_with = "duration::serialize_duration")]
+    pub timeout: Duration,
+    pub max_attempts: u32,
+    pub confirm_distance: f64,
+    pub warp_distance: f64,
+    #[serde(serialize_with = "duration::serialize_duration")]
+    pub warp_cooldown: Duration,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CommandsResolved {
+    pub prefix: String,
+    pub owners: Vec<String>,
+    pub roles: BTreeMap<String, RoleResolved>,
+    #[serde(serialize_with = "duration::serialize_duration")]
+    pub cooldown: Duration,
+    #[serde(serialize_with = "duration::serialize_durations")]
+    pub cooldowns: BTreeMap<String, Duration>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RoleResolved {
+    pub commands: Vec<String>,
+    pub players: Vec<String>,
+    pub uuids: Vec<String>,
+}
+
+impl RoleResolved {
+    pub fn grants(&self, command: &str) -> bool {
+        self.commands.iter().any(|granted| granted == "*" || granted.eq_ignore_ascii_case(command))
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct OutboxResolved {
+    #[serde(serialize_with = "duration::serialize_duration")]
+    pub min_interval: Duration,
+    pub burst: u32,
+    #[serde(serialize_with = "duration::serialize_duration")]
+    pub refill: Duration,
+    pub max_slowdown: f64,
+    #[serde(serialize_with = "duration::serialize_duration")]
+    pub recover_after: Duration,
+    pub max_queued: u32,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RestartResolved {
+    #[serde(serialize_with = "duration::serialize_duration")]
+    pub initial_delay: Duration,
+    #[serde(serialize_with = "duration::serialize_duration")]
+    pub max_delay: Duration,
+    pub jitter: f64,
+    pub max_retries: u32,
+    #[serde(serialize_with = "duration::serialize_duration")]
+    pub park_for: Duration,
+    #[serde(serialize_with = "duration::serialize_duration")]
+    pub stable_after: Duration,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
 pub struct DelayResolved {
     pub min: DelayValues,
     pub max: DelayValues,
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize)]
 pub struct DelayValues {
-    pub global: i32,
-    pub discord: i32,
-    pub invite: i32,
+    #[serde(serialize_with = "duration::serialize_duration")]
+    pub global: Duration,
+    #[serde(serialize_with = "duration::serialize_duration")]
+    pub discord: Duration,
+    #[serde(serialize_with = "duration::serialize_duration")]
+    pub invite: Duration,
 }
 
 impl Config {
-    pub fn resolve(&self, portal_name: &str) -> Result<RuntimeConfig> {
+    pub fn resolve(&self, portal_name: &str, portal_id: &str, provenance: &Provenance) -> Result<RuntimeConfig> {
         let bot = self.bot.as_ref().ok_or_else(|| anyhow!("Bot config is missing"))?;
         let nickname = bot.nickname.as_ref().ok_or_else(|| anyhow!("Bot nickname is missing"))?.clone();
-        let password = bot.password.as_ref().ok_or_else(|| anyhow!("Bot password is missing"))?.clone();
+        let (password, password_source) = secrets::resolve_password(bot, self.secrets.as_ref(), provenance)?;
+        log!(INFO, "[{}] Using bot password from {}", portal_id, password_source);
         let warp = bot.warp.as_ref().ok_or_else(|| anyhow!("Bot warp is missing"))?.clone();
         // --- Updated delay resolution logic ---
         let delay_config = self.delay.as_ref().ok_or_else(|| anyhow!("Delay config is missing"))?;
@@ -97,47 +438,263 @@ impl Config {
 
         // Extract the specific delay values, ensuring they are present within the selected min/max config sections.
         let min = DelayValues {
-            global: final_min_delay_config.global.ok_or_else(|| anyhow!("Min global delay value is missing"))?,
-            discord: final_min_delay_config.discord.ok_or_else(|| anyhow!("Min discord delay value is missing"))?,
-            invite: final_min_delay_config.invite.ok_or_else(|| anyhow!("Min invite delay value is missing"))?,
+            global: resolve_delay(final_min_delay_config.global.as_ref(), "Min global")?,
+            discord: resolve_delay(final_min_delay_config.discord.as_ref(), "Min discord")?,
+            invite: resolve_delay(final_min_delay_config.invite.as_ref(), "Min invite")?,
         };
 
         let max = DelayValues {
-            global: final_max_delay_config.global.ok_or_else(|| anyhow!("Max global delay value is missing"))?,
-            discord: final_max_delay_config.discord.ok_or_else(|| anyhow!("Max discord delay value is missing"))?,
-            invite: final_max_delay_config.invite.ok_or_else(|| anyhow!("Max invite delay value is missing"))?,
+            global: resolve_delay(final_max_delay_config.global.as_ref(), "Max global")?,
+            discord: resolve_delay(final_max_delay_config.discord.as_ref(), "Max discord")?,
+            invite: resolve_delay(final_max_delay_config.invite.as_ref(), "Max invite")?,
         };
         // --- End of updated delay resolution logic ---
 
+        let join_config = self.join.clone().unwrap_or_default();
+        let join = JoinResolved {
+            gap: resolve_or(join_config.gap.as_ref(), DEFAULT_JOIN_GAP, "Join gap")?,
+            proxy_gap: resolve_or(join_config.proxy_gap.as_ref(), DEFAULT_PROXY_GAP, "Join proxy gap")?,
+            max_authenticating: join_config.max_authenticating.unwrap_or(DEFAULT_MAX_AUTHENTICATING),
+            auth_timeout: resolve_or(join_config.auth_timeout.as_ref(), DEFAULT_AUTH_TIMEOUT, "Join auth timeout")?,
+        };
+
+        let auth_config = self.auth.clone().unwrap_or_default();
+        let auth = AuthResolved {
+            timeout: resolve_or(auth_config.timeout.as_ref(), DEFAULT_AUTH_STEP_TIMEOUT, "Auth timeout")?,
+            max_attempts: auth_config.max_attempts.unwrap_or(DEFAULT_AUTH_ATTEMPTS),
+        };
+
+        let navigation_config = self.navigation.clone().unwrap_or_default();
+        let navigation = NavigationResolved {
+            timeout: resolve_or(navigation_config.timeout.as_ref(), DEFAULT_NAVIGATION_TIMEOUT, "Navigation timeout")?,
+            max_attempts: navigation_config.max_attempts.unwrap_or(DEFAULT_NAVIGATION_ATTEMPTS),
+            confirm_distance: navigation_config.confirm_distance.unwrap_or(DEFAULT_CONFIRM_DISTANCE),
+            warp_distance: navigation_config.warp_distance.unwrap_or(DEFAULT_WARP_DISTANCE),
+            warp_cooldown: resolve_or(navigation_config.warp_cooldown.as_ref(), DEFAULT_WARP_COOLDOWN, "Warp cooldown")?,
+        };
+
+        let commands_config = self.commands.clone().unwrap_or_default();
+        let mut cooldowns = BTreeMap::new();
+        for (name, cooldown) in commands_config.cooldowns.iter().flatten() {
+            cooldowns.insert(name.clone(), resolve_cooldown(Some(cooldown), Duration::ZERO, &format!("Cooldown of command {name}"))?);
+        }
+        let commands = CommandsResolved {
+            prefix: commands_config.prefix.unwrap_or_else(|| DEFAULT_COMMAND_PREFIX.to_string()),
+            owners: commands_config.owners.unwrap_or_default(),
+            roles: commands_config.roles.unwrap_or_default()
+                .into_iter()
+                .map(|(name, role)| (name, RoleResolved {
+                    commands: role.commands.unwrap_or_default(),
+                    players: role.players.unwrap_or_default(),
+                    uuids: role.uuids.unwrap_or_default().iter().map(|uuid| uuid.to_lowercase()).collect(),
+                }))
+                .collect(),
+            cooldown: resolve_cooldown(commands_config.cooldown.as_ref(), DEFAULT_COMMAND_COOLDOWN, "Command cooldown")?,
+            cooldowns,
+        };
+
+        let outbox_config = self.outbox.clone().unwrap_or_default();
+        let outbox = OutboxResolved {
+            min_interval: resolve_or(outbox_config.min_interval.as_ref(), DEFAULT_OUTBOX_INTERVAL, "Outbox min_interval")?,
+            burst: outbox_config.burst.unwrap_or(DEFAULT_OUTBOX_BURST),
+            refill: resolve_or(outbox_config.refill.as_ref(), DEFAULT_OUTBOX_REFILL, "Outbox refill")?,
+            max_slowdown: outbox_config.max_slowdown.unwrap_or(DEFAULT_OUTBOX_MAX_SLOWDOWN),
+            recover_after: resolve_or(outbox_config.recover_after.as_ref(), DEFAULT_OUTBOX_RECOVER_AFTER, "Outbox recover_after")?,
+            max_queued: outbox_config.max_queued.unwrap_or(DEFAULT_OUTBOX_MAX_QUEUED),
+        };
+
+        let restart_config = self.restart.clone().unwrap_or_default();
+        let restart = RestartResolved {
+            initial_delay: resolve_or(restart_config.initial_delay.as_ref(), DEFAULT_RESTART_DELAY, "Restart initial delay")?,
+            max_delay: resolve_or(restart_config.max_delay.as_ref(), DEFAULT_RESTART_MAX_DELAY, "Restart max delay")?,
+            jitter: restart_config.jitter.unwrap_or(DEFAULT_RESTART_JITTER),
+            max_retries: restart_config.max_retries.unwrap_or(DEFAULT_MAX_RETRIES),
+            park_for: resolve_or(restart_config.park_for.as_ref(), DEFAULT_PARK_FOR, "Restart park duration")?,
+            stable_after: resolve_or(restart_config.stable_after.as_ref(), DEFAULT_STABLE_AFTER, "Restart stable duration")?,
+        };
 
         Ok(RuntimeConfig {
             bot: BotConfigResolved {
                 nickname,
                 password,
+                password_source,
                 warp,
                 portal: portal_name.to_string(),
+                id: portal_id.to_string(),
             },
             delay: DelayResolved { min, max },
+            join,
+            auth,
+            navigation,
+            restart,
+            commands,
+            outbox,
+            // Depends on the server directory rather than the config, set by `load_cfg`
+            patterns: Arc::default(),
+            reactions: Arc::default(),
         })
     }
 }
 
-fn load_toml_config(path: &Path) -> Result<Config> {
+fn resolve_or(value: Option<&ConfigDuration>, default: Duration, name: &str) -> Result<Duration> {
+    match value {
+        Some(value) => value.to_duration().map_err(|err| anyhow!("{} {}", name, err)),
+        None => Ok(default),
+    }
+}
+
+fn resolve_cooldown(value: Option<&ConfigDuration>, default: Duration, name: &str) -> Result<Duration> {
+    match value {
+        Some(value) => value.to_cooldown().map_err(|err| anyhow!("{} {}", name, err)),
+        None => Ok(default),
+    }
+}
+
+fn resolve_delay(value: Option<&ConfigDuration>, name: &str) -> Result<Duration> {
+    let value = value.ok_or_else(|| anyhow!("{} delay value is missing", name))?;
+    value.to_duration().map_err(|err| anyhow!("{} delay value {}", name, err))
+}
+
+/// Everything `load_cfg` resolved for one portal.
+#[derive(Debug, Clone, Default)]
+pub struct LoadedConfig {
+    pub config: Config,
+    pub runtime: RuntimeConfig,
+    pub server: ServerConfig,
+    pub proxy: ProxyConfig,
+    pub provenance: Provenance,
+    pub portal_path: PathBuf,
+    pub kick: KickClassifier,
+}
+
+fn load_toml_config(path: &Path) -> Result<(Config, toml::Table)> {
     if !path.exists() {
-        return Ok(Config::default());
+        return Ok((Config::default(), toml::Table::new()));
     }
     let content = std::fs::read_to_string(path)
         .context(format!("Failed to read config file: {}", path.display()))?;
-    let config: Config = toml::from_str(&content)
-        .context(format!("Failed to deserialize TOML file: {}", path.display()))?;
-    Ok(config)
+    // Every field is optional, so a typo like `[delays.min]` would otherwise be silently ignored
+    let mut unknown_keys = Vec::new();
+    let config: Config = serde_ignored::deserialize(toml::Deserializer::new(&content), |key| {
+        unknown_keys.push(ignored_key(&key));
+    })
+    .context(format!("Failed to deserialize TOML file: {}", path.display()))?;
+    if !unknown_keys.is_empty() {
+        return Err(anyhow!("Unknown key(s) in {}: {}", path.display(), unknown_keys.join(", ")));
+    }
+    let table: toml::Table = content.parse()
+        .context(format!("Failed to parse TOML file: {}", path.display()))?;
+    Ok((config, table))
+}
+
+/// Formats a `serde_ignored` path as a dotted TOML key, skipping the `Option` wrappers.
+fn ignored_key(path: &serde_ignored::Path) -> String {
+    use serde_ignored::Path as P;
+    match path {
+        P::Root => String::new(),
+        P::Map { parent, key } => match ignored_key(parent) {
+            parent if parent.is_empty() => key.clone(),
+            parent => format!("{parent}.{key}"),
+        },
+        P::Seq { parent, index } => format!("{}[{}]", ignored_key(parent), index),
+        P::Some { parent } | P::NewtypeStruct { parent } | P::NewtypeVariant { parent } => ignored_key(parent),
+    }
+}
+
+type Layer = (PathBuf, Config, toml::Table);
+
+/// Appends `path` to `layers`, preceded (recursively) by the files listed in its `extends`.
+/// A file reached twice is only applied at its first position; reaching a file that is
+/// still being expanded is a cycle.
+fn collect_layers(
+    path: &Path,
+    chain: &mut Vec<(PathBuf, PathBuf)>,
+    seen: &mut HashSet<PathBuf>,
+    layers: &mut Vec<Layer>,
+) -> Result<()> {
+    let identity = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    if chain.iter().any(|(_, id)| *id == identity) {
+        return Err(anyhow!("Config inheritance cycle: {} -> {}", format_chain(chain), path.display()));
+    }
+    if !seen.insert(identity.clone()) {
+        return Ok(());
+    }
+
+    let (config, table) = match load_toml_config(path) {
+        Ok(layer) => layer,
+        Err(err) if chain.is_empty() => return Err(err),
+        Err(err) => return Err(err.context(format!("Failed to load config chain: {}", chain_to(chain, path)))),
+    };
+
+    chain.push((path.to_path_buf(), identity));
+    let dir = path.parent().unwrap_or(Path::new(""));
+    for entry in config.extends.iter().flatten() {
+        let target = dir.join(entry);
+        if !target.is_file() {
+            return Err(anyhow!(
+                "Config {} extends missing file {} (chain: {})",
+                path.display(),
+                target.display(),
+                chain_to(chain, &target),
+            ));
+        }
+        collect_layers(&target, chain, seen, layers)?;
+    }
+    chain.pop();
+
+    layers.push((path.to_path_buf(), config, table));
+    Ok(())
 }
 
-pub fn load_cfg(portal_path: &Path) -> Result<(Config, RuntimeConfig, ServerConfig, ProxyConfig)> {
-    let portal_name = portal_path.file_stem()
+fn format_chain(chain: &[(PathBuf, PathBuf)]) -> String {
+    chain.iter().map(|(path, _)| path.display().to_string()).collect::<Vec<_>>().join(" -> ")
+}
+
+fn chain_to(chain: &[(PathBuf, PathBuf)], path: &Path) -> String {
+    if chain.is_empty() {
+        path.display().to_string()
+    } else {
+        format!("{} -> {}", format_chain(chain), path.display())
+    }
+}
+
+fn portal_name(portal_path: &Path) -> Result<String> {
+    portal_path.file_stem()
         .and_then(|s| s.to_str())
         .map(|s| s.to_string())
-        .ok_or_else(|| anyhow!("Could not extract portal name from path: {}", portal_path.display()))?;
+        .ok_or_else(|| anyhow!("Could not extract portal name from path: {}", portal_path.display()))
+}
+
+/// `server/portal`, the server being the name of the directory holding the portal file.
+fn portal_id(portal_path: &Path) -> Result<String> {
+    let portal_name = portal_name(portal_path)?;
+    let server = portal_path.parent()
+        .and_then(|dir| dir.file_name())
+        .and_then(|s| s.to_str())
+        .ok_or_else(|| anyhow!("Could not extract server name from path: {}", portal_path.display()))?;
+    Ok(format!("{server}/{portal_name}"))
+}
+
+/// Merges `paths` in order, each preceded by the files it extends.
+fn merge_layers(paths: &[&Path]) -> Result<(Config, Provenance)> {
+    let mut layers = Vec::new();
+    let mut seen = HashSet::new();
+    for path in paths {
+        collect_layers(path, &mut Vec::new(), &mut seen, &mut layers)?;
+    }
+
+    let mut merged_config = Config::default();
+    let mut provenance = Provenance::default();
+    for (path, config, table) in layers {
+        merged_config.merge(config);
+        provenance.record(&path, &table);
+    }
+    Ok((merged_config, provenance))
+}
+
+/// Loads and merges the config layers of a portal without validating or resolving them.
+pub fn merge_cfg(portal_path: &Path) -> Result<(Config, Provenance)> {
+    let portal_name = portal_name(portal_path)?;
 
     let portal_dir = portal_path.parent()
         .ok_or_else(|| anyhow!("Invalid portal path (no parent directory): {}", portal_path.display()))?;
@@ -152,143 +709,180 @@ pub fn load_cfg(portal_path: &Path) -> Result<(Config, RuntimeConfig, ServerConf
     println!("Loading portal config from: {} (Portal Name: {})", portal_path.display(), portal_name);
 
 
-    let default_config = load_toml_config(&default_path)?;
-    let server_config = load_toml_config(&server_path)?;
-    let portal_config = load_toml_config(portal_path)?;
+    // Later layers win: default.toml -> all.toml -> portal file
+    let (mut merged_config, provenance) = merge_layers(&[&default_path, &server_path, portal_path])?;
+
+    // A portal without a file of its own is synthesized from the `portals` set
+    if !portal_path.exists() {
+        let portal_set = merged_config.portal_set()
+            .map_err(|err| anyhow!("Invalid portal set in {}: {}", server_path.display(), err))?;
+        if !portal_set.contains(&portal_name) {
+            return Err(anyhow!(
+                "Portal config {} does not exist and '{}' is not in the portal set of {}",
+                portal_path.display(),
+                portal_name,
+                server_path.display(),
+            ));
+        }
+    }
+    merged_config.apply_portal_template(&portal_name);
+
+    Ok((merged_config, provenance))
+}
+
+pub fn load_cfg(portal_path: &Path) -> Result<LoadedConfig> {
+    let portal_name = portal_name(portal_path)?;
+    let portal_id = portal_id(portal_path)?;
+    let (merged_config, provenance) = merge_cfg(portal_path)?;
 
-    let merged_config = merge_configs(&default_config, &server_config, &portal_config);
+    let issues = merged_config.validate(&provenance, portal_path);
+    if !issues.is_empty() {
+        return Err(ConfigErrors(issues).into());
+    }
 
-    let runtime_config = merged_config.resolve(&portal_name)
+    let mut runtime_config = merged_config.resolve(&portal_name, &portal_id, &provenance)
         .context("Failed to resolve merged configuration")?;
+    runtime_config.patterns = load_patterns(portal_path.parent().unwrap_or(Path::new("")))
+        .map_err(|issues| anyhow!("Invalid chat patterns: {}", issues[0]))?;
+    runtime_config.reactions = Reactions::compile(merged_config.chat.as_ref())
+        .map(Arc::new)
+        .map_err(|err| anyhow!("Failed to compile chat reactions: {}", err))?;
 
     let server_config_to_return = merged_config.server.clone()
         .ok_or_else(|| anyhow!("Merged configuration is missing 'server' section"))?;
     let proxy_config_to_return = merged_config.proxy.clone().unwrap_or_default();
-    Ok((merged_config, runtime_config, server_config_to_return, proxy_config_to_return))
+    let kick = KickClassifier::compile(merged_config.kick.as_ref())
+        .map_err(|err| anyhow!("Failed to compile kick rules: {}", err))?;
+    Ok(LoadedConfig {
+        config: merged_config,
+        runtime: runtime_config,
+        server: server_config_to_return,
+        proxy: proxy_config_to_return,
+        provenance,
+        portal_path: portal_path.to_path_buf(),
+        kick,
+    })
 }
 
-fn merge_configs(default: &Config, server: &Config, portal: &Config) -> Config {
-    // --- BotConfig ---
-    let bot_nickname = portal.bot.as_ref().and_then(|b| b.nickname.clone())
-        .or_else(|| server.bot.as_ref().and_then(|b| b.nickname.clone()))
-        .or_else(|| default.bot.as_ref().and_then(|b| b.nickname.clone()));
-
-    let bot_password = portal.bot.as_ref().and_then(|b| b.password.clone())
-        .or_else(|| server.bot.as_ref().and_then(|b| b.password.clone()))
-        .or_else(|| default.bot.as_ref().and_then(|b| b.password.clone()));
-
-    let bot_warp = portal.bot.as_ref().and_then(|b| b.warp.clone())
-        .or_else(|| server.bot.as_ref().and_then(|b| b.warp.clone()))
-        .or_else(|| default.bot.as_ref().and_then(|b| b.warp.clone()));
-
-    let merged_bot = if bot_nickname.is_some() || bot_password.is_some() || bot_warp.is_some() {
-        Some(BotConfig {
-            nickname: bot_nickname,
-            password: bot_password,
-            warp: bot_warp,
-        })
-    } else {
-        None
-    };
-
-    // --- ServerConfig ---
-    let server_host = portal.server.as_ref().and_then(|s| s.host.clone())
-        .or_else(|| server.server.as_ref().and_then(|s| s.host.clone()))
-        .or_else(|| default.server.as_ref().and_then(|s| s.host.clone()));
-
-    let server_port = portal.server.as_ref().and_then(|s| s.port)
-        .or_else(|| server.server.as_ref().and_then(|s| s.port))
-        .or_else(|| default.server.as_ref().and_then(|s| s.port));
-
-    let server_version = portal.server.as_ref().and_then(|s| s.version.clone())
-        .or_else(|| server.server.as_ref().and_then(|s| s.version.clone()))
-        .or_else(|| default.server.as_ref().and_then(|s| s.version.clone()));
-
-    let merged_server = if server_host.is_some() || server_port.is_some() || server_version.is_some() {
-        Some(ServerConfig {
-            host: server_host,
-            port: server_port,
-            version: server_version,
-        })
-    } else {
-        None
-    };
+/// JSON Schema of the config file format, for editor TOML plugins.
+pub fn json_schema() -> serde_json::Value {
+    serde_json::to_value(schemars::schema_for!(Config)).unwrap_or_default()
+}
 
-    // --- ProxyConfig ---
-    let proxy_host = portal.proxy.as_ref().and_then(|p| p.host.clone())
-        .or_else(|| server.proxy.as_ref().and_then(|p| p.host.clone()))
-        .or_else(|| default.proxy.as_ref().and_then(|p| p.host.clone()));
+/// Finds portal configs under `root`: every `.toml` file other than `all.toml` and
+/// `patterns.toml` in a directory that has an `all.toml`, plus the portals its
+/// `portals` set synthesizes.
+/// A file path is returned as is.
+pub fn discover_portals(root: &Path) -> Result<Vec<PathBuf>> {
+    if root.is_file() {
+        return Ok(vec![root.to_path_buf()]);
+    }
 
-    let proxy_port = portal.proxy.as_ref().and_then(|p| p.port)
-        .or_else(|| server.proxy.as_ref().and_then(|p| p.port))
-        .or_else(|| default.proxy.as_ref().and_then(|p| p.port));
+    let mut entries = std::fs::read_dir(root)
+        .context(format!("Failed to read config directory: {}", root.display()))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort();
+
+    let is_server_dir = root.join("all.toml").is_file();
+    let mut portals = Vec::new();
+    if is_server_dir {
+        let server_path = root.join("all.toml");
+        let default_path = root.parent().unwrap_or(Path::new("")).join("default.toml");
+        let (server_config, _) = merge_layers(&[&default_path, &server_path])?;
+        let portal_set = server_config.portal_set()
+            .map_err(|err| anyhow!("Invalid portal set in {}: {}", server_path.display(), err))?;
+        portals.extend(portal_set.iter().map(|name| root.join(format!("{name}.toml"))));
+    }
+    for path in entries {
+        if path.is_dir() {
+            portals.extend(discover_portals(&path)?);
+        } else if is_server_dir
+            && path.extension().is_some_and(|ext| ext == "toml")
+            && path.file_name().is_some_and(|name| name != "all.toml" && name != PATTERNS_FILE)
+        {
+            portals.push(path);
+        }
+    }
+    portals.sort();
+    portals.dedup();
+    Ok(portals)
+}
 
-    let merged_proxy = if proxy_host.is_some() || proxy_port.is_some() {
-        Some(ProxyConfig {
-            host: proxy_host,
-            port: proxy_port,
-        })
-    } else {
-        None
-    };
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::scratch_dir;
+
+    /// Writes `files` into a fresh scratch directory.
+    fn tree(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = scratch_dir(name);
+        for (path, content) in files {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+        dir
+    }
 
-    // --- DelayConfig ---
-    // Сначала объединяем поля для min delay
-    let min_delay_global = portal.delay.as_ref().and_then(|d| d.min.as_ref()).and_then(|m| m.global)
-        .or_else(|| server.delay.as_ref().and_then(|d| d.min.as_ref()).and_then(|m| m.global))
-        .or_else(|| default.delay.as_ref().and_then(|d| d.min.as_ref()).and_then(|m| m.global));
-    let min_delay_discord = portal.delay.as_ref().and_then(|d| d.min.as_ref()).and_then(|m| m.discord)
-        .or_else(|| server.delay.as_ref().and_then(|d| d.min.as_ref()).and_then(|m| m.discord))
-        .or_else(|| default.delay.as_ref().and_then(|d| d.min.as_ref()).and_then(|m| m.discord));
-    let min_delay_invite = portal.delay.as_ref().and_then(|d| d.min.as_ref()).and_then(|m| m.invite)
-        .or_else(|| server.delay.as_ref().and_then(|d| d.min.as_ref()).and_then(|m| m.invite))
-        .or_else(|| default.delay.as_ref().and_then(|d| d.min.as_ref()).and_then(|m| m.invite));
-
-    let merged_min_delay = if min_delay_global.is_some() || min_delay_discord.is_some() || min_delay_invite.is_some() {
-        Some(Delay {
-            global: min_delay_global,
-            discord: min_delay_discord,
-            invite: min_delay_invite,
-        })
-    } else {
-        None
-    };
+    fn nickname(config: &Config) -> Option<&str> {
+        config.bot.as_ref()?.nickname.as_deref()
+    }
 
-    // Теперь объединяем поля для max delay, аналогично min
-    let max_delay_global = portal.delay.as_ref().and_then(|d| d.max.as_ref()).and_then(|m| m.global)
-        .or_else(|| server.delay.as_ref().and_then(|d| d.max.as_ref()).and_then(|m| m.global))
-        .or_else(|| default.delay.as_ref().and_then(|d| d.max.as_ref()).and_then(|m| m.global));
-    let max_delay_discord = portal.delay.as_ref().and_then(|d| d.max.as_ref()).and_then(|m| m.discord)
-        .or_else(|| server.delay.as_ref().and_then(|d| d.max.as_ref()).and_then(|m| m.discord))
-        .or_else(|| default.delay.as_ref().and_then(|d| d.max.as_ref()).and_then(|m| m.discord));
-    let max_delay_invite = portal.delay.as_ref().and_then(|d| d.max.as_ref()).and_then(|m| m.invite)
-        .or_else(|| server.delay.as_ref().and_then(|d| d.max.as_ref()).and_then(|m| m.invite))
-        .or_else(|| default.delay.as_ref().and_then(|d| d.max.as_ref()).and_then(|m| m.invite));
-
-    let merged_max_delay = if max_delay_global.is_some() || max_delay_discord.is_some() || max_delay_invite.is_some() {
-        Some(Delay {
-            global: max_delay_global,
-            discord: max_delay_discord,
-            invite: max_delay_invite,
-        })
-    } else {
-        None
-    };
+    #[test]
+    fn extends_are_applied_before_the_file_in_order() {
+        let dir = tree("extends-order", &[
+            ("base.toml", "[bot]\nnickname = \"Base\"\nwarp = \"base\"\n"),
+            ("shop.toml", "extends = [\"base.toml\"]\n[bot]\nwarp = \"shop\"\n"),
+            ("night.toml", "[bot]\nnickname = \"Night\"\n"),
+            ("srv/s1.toml", "extends = [\"../shop.toml\", \"../night.toml\"]\n[server]\nhost = \"mc.example.com\"\n"),
+        ]);
+        let (config, provenance) = merge_layers(&[&dir.join("srv/s1.toml")]).unwrap();
+        assert_eq!(nickname(&config), Some("Night"));
+        assert_eq!(config.bot.as_ref().unwrap().warp.as_deref(), Some("shop"));
+        let chain: Vec<_> = provenance.files().iter().map(|path| path.file_name().unwrap().to_str().unwrap()).collect();
+        assert_eq!(chain, ["base.toml", "shop.toml", "night.toml", "s1.toml"]);
+        assert_eq!(provenance.source("bot.warp"), Source::File(dir.join("srv/../shop.toml")));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
 
-    let merged_delay = if merged_min_delay.is_some() || merged_max_delay.is_some() {
-        Some(DelayConfig {
-            min: merged_min_delay,
-            max: merged_max_delay,
-        })
-    } else {
-        None
-    };
+    #[test]
+    fn shared_ancestor_is_applied_once() {
+        let dir = tree("extends-diamond", &[
+            ("base.toml", "[bot]\nnickname = \"Base\"\n"),
+            ("a.toml", "extends = [\"base.toml\"]\n[bot]\nnickname = \"A\"\n"),
+            ("b.toml", "extends = [\"base.toml\"]\n[bot]\nwarp = \"b\"\n"),
+            ("top.toml", "extends = [\"a.toml\", \"b.toml\"]\n"),
+        ]);
+        let (config, provenance) = merge_layers(&[&dir.join("top.toml")]).unwrap();
+        // base.toml is not applied again before b.toml, so a.toml's nickname stays
+        assert_eq!(nickname(&config), Some("A"));
+        assert_eq!(provenance.files().len(), 4);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
 
+    #[test]
+    fn reports_cycles_and_missing_files_with_the_chain() {
+        let dir = tree("extends-cycle", &[
+            ("a.toml", "extends = [\"b.toml\"]\n"),
+            ("b.toml", "extends = [\"a.toml\"]\n"),
+            ("c.toml", "extends = [\"missing.toml\"]\n"),
+        ]);
+        let err = merge_layers(&[&dir.join("a.toml")]).unwrap_err().to_string();
+        assert!(err.starts_with("Config inheritance cycle:"), "{err}");
+        assert!(err.contains("a.toml -> ") && err.contains("b.toml -> "), "{err}");
+
+        let err = merge_layers(&[&dir.join("c.toml")]).unwrap_err().to_string();
+        assert!(err.contains("extends missing file"), "{err}");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
 
-    Config {
-        bot: merged_bot,
-        server: merged_server,
-        proxy: merged_proxy,
-        delay: merged_delay,
+    #[test]
+    fn portal_id_includes_the_server() {
+        assert_eq!(portal_id(Path::new("cfg/mw/s1.toml")).unwrap(), "mw/s1");
+        assert_eq!(portal_id(Path::new("cfg/mb/s1.toml")).unwrap(), "mb/s1");
     }
 }
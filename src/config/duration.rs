@@ -0,0 +1,188 @@
+use std::{collections::BTreeMap, fmt, time::Duration};
+
+use schemars::JsonSchema;
+use serde::{ser::SerializeMap, Deserialize, Serialize, Serializer};
+
+use super::Merge;
+
+/// A duration as written in config files: a plain integer number of seconds, or a
+/// string of `<number><unit>` parts such as `"150s"`, `"10m"` or `"1h30m"`.
+///
+/// Kept as written until resolution so that errors can point at the exact key.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(untagged)]
+pub enum ConfigDuration {
+    Seconds(i64),
+    Text(String),
+}
+
+impl Merge for ConfigDuration {
+    fn merge(&mut self, overlay: Self) {
+        *self = overlay;
+    }
+}
+
+impl fmt::Display for ConfigDuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigDuration::Seconds(secs) => write!(f, "{secs}"),
+            ConfigDuration::Text(text) => write!(f, "{text:?}"),
+        }
+    }
+}
+
+impl ConfigDuration {
+    /// Converts to a `Duration`, rejecting malformed, negative and zero values.
+    pub fn to_duration(&self) -> Result<Duration, String> {
+        let duration = self.to_cooldown()?;
+        if duration.is_zero() {
+            return Err(format!("{self} must be greater than zero"));
+        }
+        Ok(duration)
+    }
+
+    /// Converts a cooldown to a `Duration`, rejecting malformed and negative values. Zero
+    /// turns the cooldown off.
+    pub fn to_cooldown(&self) -> Result<Duration, String> {
+        match self {
+            ConfigDuration::Seconds(secs) if *secs < 0 => Err(format!("{secs} must not be negative")),
+            ConfigDuration::Seconds(secs) => Ok(Duration::from_secs(*secs as u64)),
+            ConfigDuration::Text(text) => parse_duration(text),
+        }
+    }
+}
+
+/// Parses `"90"`, `"150s"`, `"10m"`, `"1h30m"`, `"1d"`. A bare number means seconds.
+pub fn parse_duration(text: &str) -> Result<Duration, String> {
+    let trimmed = text.trim();
+    if trimmed.starts_with('-') {
+        return Err(format!("{text:?} must not be negative"));
+    }
+    if let Ok(secs) = trimmed.parse::<u64>() {
+        return Ok(Duration::from_secs(secs));
+    }
+
+    let invalid = || format!("{text:?} is not a duration, expected e.g. \"150s\", \"10m\" or \"1h30m\"");
+    let mut total = Duration::ZERO;
+    let mut rest = trimmed;
+    if rest.is_empty() {
+        return Err(invalid());
+    }
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        if digits == 0 {
+            return Err(invalid());
+        }
+        let value: u64 = rest[..digits].parse().map_err(|_| invalid())?;
+        rest = &rest[digits..];
+
+        let unit_len = rest.find(|c: char| c.is_ascii_digit() || c.is_whitespace()).unwrap_or(rest.len());
+        let part = match &rest[..unit_len] {
+            "ms" => Some(Duration::from_millis(value)),
+            "s" => Some(Duration::from_secs(value)),
+            "m" => value.checked_mul(60).map(Duration::from_secs),
+            "h" => value.checked_mul(60 * 60).map(Duration::from_secs),
+            "d" => value.checked_mul(60 * 60 * 24).map(Duration::from_secs),
+            _ => return Err(invalid()),
+        };
+        total = part
+            .and_then(|part| total.checked_add(part))
+            .ok_or_else(|| format!("{text:?} is too long"))?;
+        rest = rest[unit_len..].trim_start();
+    }
+    Ok(total)
+}
+
+/// Formats a duration the way it would be written in a config file, e.g. `1h30m`.
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let millis = duration.subsec_millis();
+    let (hours, minutes, seconds) = (secs / 3600, secs / 60 % 60, secs % 60);
+
+    let mut out = String::new();
+    if hours > 0 {
+        out.push_str(&format!("{hours}h"));
+    }
+    if minutes > 0 {
+        out.push_str(&format!("{minutes}m"));
+    }
+    if seconds > 0 || (out.is_empty() && millis == 0) {
+        out.push_str(&format!("{seconds}s"));
+    }
+    if millis > 0 {
+        out.push_str(&format!("{millis}ms"));
+    }
+    out
+}
+
+/// `serialize_with` helper printing durations in config notation.
+pub fn serialize_duration<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&format_duration(*duration))
+}
+
+/// Same as [`serialize_duration`] for a map of durations.
+pub fn serialize_durations<S: Serializer>(durations: &BTreeMap<String, Duration>, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut map = serializer.serialize_map(Some(durations.len()))?;
+    for (key, duration) in durations {
+        map.serialize_entry(key, &format_duration(*duration))?;
+    }
+    map.end()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_every_unit() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_duration("150s"), Ok(Duration::from_secs(150)));
+        assert_eq!(parse_duration("10m"), Ok(Duration::from_secs(600)));
+        assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(7200)));
+        assert_eq!(parse_duration("1d"), Ok(Duration::from_secs(86400)));
+    }
+
+    #[test]
+    fn adds_up_combined_values() {
+        assert_eq!(parse_duration("1h30m"), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_duration(" 1m 30s "), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("1s500ms"), Ok(Duration::from_millis(1500)));
+    }
+
+    #[test]
+    fn rejects_malformed_values() {
+        for text in ["", "s", "10x", "1.5h", "m10", "-5s", "10 m"] {
+            assert!(parse_duration(text).is_err(), "{text:?} should be rejected");
+        }
+    }
+
+    #[test]
+    fn rejects_zero_and_negative_config_values() {
+        assert!(ConfigDuration::Seconds(0).to_duration().is_err());
+        assert!(ConfigDuration::Seconds(-1).to_duration().is_err());
+        assert!(ConfigDuration::Text("0m".to_string()).to_duration().is_err());
+        assert_eq!(ConfigDuration::Seconds(5).to_duration(), Ok(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn cooldowns_may_be_zero() {
+        assert_eq!(ConfigDuration::Seconds(0).to_cooldown(), Ok(Duration::ZERO));
+        assert_eq!(ConfigDuration::Text("0s".to_string()).to_cooldown(), Ok(Duration::ZERO));
+        assert!(ConfigDuration::Seconds(-1).to_cooldown().is_err());
+    }
+
+    #[test]
+    fn rejects_overflow() {
+        assert!(parse_duration("999999999999999d").unwrap_err().contains("too long"));
+        assert!(parse_duration("18446744073709551615s1s").unwrap_err().contains("too long"));
+        assert!(parse_duration("99999999999999999999").is_err());
+    }
+
+    #[test]
+    fn formats_like_config_values() {
+        assert_eq!(format_duration(Duration::from_secs(5400)), "1h30m");
+        assert_eq!(format_duration(Duration::from_millis(1500)), "1s500ms");
+        assert_eq!(format_duration(Duration::ZERO), "0s");
+    }
+}
@@ -0,0 +1,231 @@
+use std::{fmt, time::Duration};
+
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::{duration, KickConfig, Merge};
+
+const DEFAULT_KICK_DELAY: Duration = Duration::from_secs(30);
+
+/// Why a bot was disconnected, as told by the first matching `kick.rules` entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum KickCategory {
+    ServerRestart,
+    Flood,
+    Ban,
+    WrongPassword,
+    Whitelist,
+    Unknown,
+}
+
+impl KickCategory {
+    /// Action taken when the matching rule sets none.
+    pub fn default_action(self) -> KickAction {
+        match self {
+            KickCategory::ServerRestart | KickCategory::Flood => KickAction::Delay,
+            KickCategory::Ban | KickCategory::Whitelist => KickAction::Stop,
+            KickCategory::WrongPassword => KickAction::Alert,
+            KickCategory::Unknown => KickAction::Backoff,
+        }
+    }
+}
+
+impl fmt::Display for KickCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            KickCategory::ServerRestart => "server restart",
+            KickCategory::Flood => "flood",
+            KickCategory::Ban => "ban",
+            KickCategory::WrongPassword => "wrong password",
+            KickCategory::Whitelist => "whitelist",
+            KickCategory::Unknown => "unknown",
+        };
+        f.write_str(name)
+    }
+}
+
+/// What the supervisor does after a disconnect.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum KickAction {
+    /// Rejoin right away.
+    Reconnect,
+    /// Rejoin after the rule's `delay`, without counting a failure.
+    Delay,
+    /// Count a failure and rejoin with the usual backoff.
+    Backoff,
+    /// Stop the portal until its config changes.
+    Stop,
+    /// Log an alert, then behave like `backoff`.
+    Alert,
+}
+
+impl fmt::Display for KickAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            KickAction::Reconnect => "reconnect",
+            KickAction::Delay => "delay",
+            KickAction::Backoff => "backoff",
+            KickAction::Stop => "stop",
+            KickAction::Alert => "alert",
+        };
+        f.write_str(name)
+    }
+}
+
+impl Merge for KickCategory {
+    fn merge(&mut self, overlay: Self) {
+        *self = overlay;
+    }
+}
+
+impl Merge for KickAction {
+    fn merge(&mut self, overlay: Self) {
+        *self = overlay;
+    }
+}
+
+/// Classification of one disconnect reason.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct KickVerdict {
+    pub category: KickCategory,
+    pub action: KickAction,
+    /// Only used by the `delay` action.
+    #[serde(serialize_with = "duration::serialize_duration")]
+    pub delay: Duration,
+    /// Index of the matching rule, `None` when no rule matched.
+    pub rule: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+struct KickMatcher {
+    pattern: Regex,
+    category: KickCategory,
+    action: KickAction,
+    delay: Duration,
+}
+
+/// The compiled `kick.rules` of a portal.
+#[derive(Debug, Clone, Default)]
+pub struct KickClassifier {
+    rules: Vec<KickMatcher>,
+    delay: Duration,
+}
+
+impl KickClassifier {
+    /// Compiles `kick.rules` in order, failing on the first bad pattern or delay.
+    pub fn compile(config: Option<&KickConfig>) -> Result<Self, String> {
+        let config = config.cloned().unwrap_or_default();
+        let delay = match &config.delay {
+            Some(delay) => delay.to_duration().map_err(|err| format!("kick.delay: {err}"))?,
+            None => DEFAULT_KICK_DELAY,
+        };
+
+        let mut rules = Vec::new();
+        for (index, rule) in config.rules.iter().flatten().enumerate() {
+            let number = index + 1;
+            let pattern = rule.pattern.as_deref().ok_or_else(|| format!("rule {number}: pattern is missing"))?;
+            let pattern = Regex::new(pattern).map_err(|err| format!("rule {number}: {err}"))?;
+            let category = rule.category.ok_or_else(|| format!("rule {number}: category is missing"))?;
+            let rule_delay = match &rule.delay {
+                Some(rule_delay) => rule_delay.to_duration().map_err(|err| format!("rule {number}: delay {err}"))?,
+                None => delay,
+            };
+            rules.push(KickMatcher {
+                pattern,
+                category,
+                action: rule.action.unwrap_or(category.default_action()),
+                delay: rule_delay,
+            });
+        }
+        Ok(Self { rules, delay })
+    }
+
+    pub fn classify(&self, reason: &str) -> KickVerdict {
+        match self.rules.iter().position(|rule| rule.pattern.is_match(reason)) {
+            Some(index) => {
+                let rule = &self.rules[index];
+                KickVerdict {
+                    category: rule.category,
+                    action: rule.action,
+                    delay: rule.delay,
+                    rule: Some(index),
+                }
+            }
+            None => KickVerdict {
+                category: KickCategory::Unknown,
+                action: KickCategory::Unknown.default_action(),
+                delay: self.delay,
+                rule: None,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn classifier(toml: &str) -> KickClassifier {
+        let config: Config = toml::from_str(toml).unwrap();
+        KickClassifier::compile(config.kick.as_ref()).unwrap()
+    }
+
+    const RULES: &str = r#"
+        [kick]
+        delay = "45s"
+
+        [[kick.rules]]
+        pattern = "(?i)restart"
+        category = "server_restart"
+        delay = "1m"
+
+        [[kick.rules]]
+        pattern = "(?i)banned"
+        category = "ban"
+
+        [[kick.rules]]
+        pattern = "(?i)ban"
+        category = "flood"
+        action = "reconnect"
+    "#;
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let kick = classifier(RULES);
+        let verdict = kick.classify("Server is restarting");
+        assert_eq!((verdict.category, verdict.action, verdict.delay, verdict.rule),
+            (KickCategory::ServerRestart, KickAction::Delay, Duration::from_secs(60), Some(0)));
+
+        let verdict = kick.classify("You are BANNED");
+        assert_eq!((verdict.category, verdict.action, verdict.rule), (KickCategory::Ban, KickAction::Stop, Some(1)));
+    }
+
+    #[test]
+    fn rules_override_the_category_action() {
+        let verdict = classifier(RULES).classify("ban wave");
+        assert_eq!((verdict.category, verdict.action, verdict.delay), (KickCategory::Flood, KickAction::Reconnect, Duration::from_secs(45)));
+    }
+
+    #[test]
+    fn unmatched_reasons_are_unknown() {
+        let verdict = classifier(RULES).classify("Timed out");
+        assert_eq!(verdict, KickVerdict {
+            category: KickCategory::Unknown,
+            action: KickAction::Backoff,
+            delay: Duration::from_secs(45),
+            rule: None,
+        });
+        assert_eq!(classifier("").classify("Timed out").delay, DEFAULT_KICK_DELAY);
+    }
+
+    #[test]
+    fn compile_names_the_broken_rule() {
+        let config: Config = toml::from_str("[[kick.rules]]\npattern = \"(\"\ncategory = \"ban\"\n").unwrap();
+        let err = KickClassifier::compile(config.kick.as_ref()).unwrap_err();
+        assert!(err.starts_with("rule 1: "), "{err}");
+    }
+}
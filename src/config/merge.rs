@@ -0,0 +1,151 @@
+use std::collections::{btree_map::Entry, BTreeMap};
+
+/// Layered merge of config values: `overlay` wins, field by field.
+///
+/// Leaf values are simply replaced, `Option`s keep the base value when the overlay
+/// is `None`, and sections declared through [`config_sections!`] merge every field
+/// recursively, so nesting works to any depth.
+pub trait Merge {
+    fn merge(&mut self, overlay: Self);
+}
+
+impl<T: Merge> Merge for Option<T> {
+    fn merge(&mut self, overlay: Self) {
+        match (self.as_mut(), overlay) {
+            (Some(base), Some(overlay)) => base.merge(overlay),
+            (None, Some(overlay)) => *self = Some(overlay),
+            (_, None) => {}
+        }
+    }
+}
+
+impl<T> Merge for Vec<T> {
+    fn merge(&mut self, overlay: Self) {
+        *self = overlay;
+    }
+}
+
+/// Maps merge key by key, so an overlay only changes the entries it lists.
+impl<K: Ord, V: Merge> Merge for BTreeMap<K, V> {
+    fn merge(&mut self, overlay: Self) {
+        for (key, value) in overlay {
+            match self.entry(key) {
+                Entry::Occupied(mut entry) => entry.get_mut().merge(value),
+                Entry::Vacant(entry) => {
+                    entry.insert(value);
+                }
+            }
+        }
+    }
+}
+
+macro_rules! merge_by_replace {
+    ($($ty:ty),* $(,)?) => {
+        $(
+            impl Merge for $ty {
+                fn merge(&mut self, overlay: Self) {
+                    *self = overlay;
+                }
+            }
+        )*
+    };
+}
+
+merge_by_replace!(String, bool, u16, u32, u64, i32, i64, f64);
+
+/// Declares config section structs together with their [`Merge`] impl.
+///
+/// Every field takes part in the merge, so adding an option to a section (or a whole
+/// new section to `Config`) is enough for it to be inherited from default.toml and
+/// all.toml without touching the loader.
+macro_rules! config_sections {
+    ($(
+        $(#[$meta:meta])*
+        pub struct $name:ident {
+            $(
+                $(#[$field_meta:meta])*
+                pub $field:ident: $ty:ty
+            ),* $(,)?
+        }
+    )*) => {
+        $(
+            $(#[$meta])*
+            #[derive(Debug, Deserialize, Serialize, Default, Clone, PartialEq, JsonSchema)]
+            #[schemars(deny_unknown_fields)]
+            pub struct $name {
+                $(
+                    $(#[$field_meta])*
+                    pub $field: $ty,
+                )*
+            }
+
+            impl $crate::config::merge::Merge for $name {
+                fn merge(&mut self, overlay: Self) {
+                    $(self.$field.merge(overlay.$field);)*
+                }
+            }
+        )*
+    };
+}
+
+pub(crate) use config_sections;
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::Merge;
+    use crate::config::{BotConfig, CommandsConfig, Config, ConfigDuration, Delay, DelayConfig};
+
+    #[test]
+    fn overlay_wins_field_by_field() {
+        let mut base = Config {
+            bot: Some(BotConfig {
+                nickname: Some("Base".to_string()),
+                warp: Some("spawn".to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        base.merge(Config {
+            bot: Some(BotConfig { nickname: Some("Portal".to_string()), ..Default::default() }),
+            ..Default::default()
+        });
+
+        let bot = base.bot.unwrap();
+        assert_eq!(bot.nickname.as_deref(), Some("Portal"));
+        assert_eq!(bot.warp.as_deref(), Some("spawn"));
+    }
+
+    #[test]
+    fn nested_sections_merge_recursively() {
+        let config = |global, invite| Config {
+            delay: Some(DelayConfig { min: Some(Delay { global, invite, ..Default::default() }), max: None }),
+            ..Default::default()
+        };
+        let mut base = config(Some(ConfigDuration::Seconds(10)), Some(ConfigDuration::Seconds(20)));
+        base.merge(config(None, Some(ConfigDuration::Text("1m".to_string()))));
+
+        let min = base.delay.unwrap().min.unwrap();
+        assert_eq!(min.global, Some(ConfigDuration::Seconds(10)));
+        assert_eq!(min.invite, Some(ConfigDuration::Text("1m".to_string())));
+    }
+
+    #[test]
+    fn lists_are_replaced() {
+        let mut base = Some(vec![1, 2, 3]);
+        base.merge(Some(vec![4]));
+        assert_eq!(base, Some(vec![4]));
+    }
+
+    #[test]
+    fn maps_merge_key_by_key() {
+        let cooldowns = |entries: &[(&str, i64)]| -> BTreeMap<_, _> {
+            entries.iter().map(|(name, secs)| (name.to_string(), ConfigDuration::Seconds(*secs))).collect()
+        };
+        let commands = |entries| CommandsConfig { cooldowns: Some(cooldowns(entries)), ..Default::default() };
+        let mut base = commands(&[("say", 10), ("warp", 30)]);
+        base.merge(commands(&[("warp", 5), ("status", 1)]));
+        assert_eq!(base.cooldowns, Some(cooldowns(&[("say", 10), ("status", 1), ("warp", 5)])));
+    }
+}
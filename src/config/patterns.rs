@@ -0,0 +1,249 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use regex::{Captures, Regex};
+
+/// File next to `all.toml` with the chat patterns of that server.
+pub const PATTERNS_FILE: &str = "patterns.toml";
+
+/// A named chat pattern. Servers override any of them in `patterns.toml`, under the
+/// section and name of [`Pattern::key`]; the others keep their built-in value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Pattern {
+    RegisterPrompt,
+    LoginPrompt,
+    WrongPassword,
+    AlreadyRegistered,
+    AuthSuccess,
+    SessionRestored,
+    PortalJoined,
+    /// The server moved the bot back to the lobby.
+    BackInLobby,
+    Teleported,
+    Muted,
+    Banned,
+    /// The server refused a message for being sent too soon after the previous one.
+    TooFast,
+    /// Chat line formats, with `sender` and `message` groups and optional
+    /// `rank`, `clan` and `recipient` ones.
+    ChatGlobal,
+    ChatLocal,
+    ChatClan,
+    ChatPersonal,
+}
+
+impl Pattern {
+    pub const ALL: [Pattern; 16] = [
+        Pattern::RegisterPrompt,
+        Pattern::LoginPrompt,
+        Pattern::WrongPassword,
+        Pattern::AlreadyRegistered,
+        Pattern::AuthSuccess,
+        Pattern::SessionRestored,
+        Pattern::PortalJoined,
+        Pattern::BackInLobby,
+        Pattern::Teleported,
+        Pattern::Muted,
+        Pattern::Banned,
+        Pattern::TooFast,
+        Pattern::ChatGlobal,
+        Pattern::ChatLocal,
+        Pattern::ChatClan,
+        Pattern::ChatPersonal,
+    ];
+
+    /// `section.name` of the pattern in `patterns.toml`.
+    pub fn key(self) -> &'static str {
+        match self {
+            Pattern::RegisterPrompt => "auth.register_prompt",
+            Pattern::LoginPrompt => "auth.login_prompt",
+            Pattern::WrongPassword => "auth.wrong_password",
+            Pattern::AlreadyRegistered => "auth.already_registered",
+            Pattern::AuthSuccess => "auth.success",
+            Pattern::SessionRestored => "auth.session_restored",
+            Pattern::PortalJoined => "portal.joined",
+            Pattern::BackInLobby => "portal.lobby",
+            Pattern::Teleported => "teleport.done",
+            Pattern::Muted => "moderation.muted",
+            Pattern::Banned => "moderation.banned",
+            Pattern::TooFast => "moderation.too_fast",
+            Pattern::ChatGlobal => "chat.global",
+            Pattern::ChatLocal => "chat.local",
+            Pattern::ChatClan => "chat.clan",
+            Pattern::ChatPersonal => "chat.personal",
+        }
+    }
+
+    fn is_chat_format(self) -> bool {
+        matches!(self, Pattern::ChatGlobal | Pattern::ChatLocal | Pattern::ChatClan | Pattern::ChatPersonal)
+    }
+
+    /// Built-in value, generic enough for most AuthMe-style servers. Chat formats
+    /// differ too much between servers to have one.
+    fn builtin(self) -> Option<&'static str> {
+        match self {
+            Pattern::RegisterPrompt => Some(r"(?i)(/reg(ister)?\b|зарегистрируйтесь)"),
+            Pattern::LoginPrompt => Some(r"(?i)(/l(ogin)?\b|авторизуйтесь|войдите в аккаунт)"),
+            Pattern::WrongPassword => Some(r"(?i)(wrong password|неверный пароль|неправильный пароль)"),
+            Pattern::AlreadyRegistered => Some(r"(?i)(already registered|уже зарегистрирован)"),
+            Pattern::AuthSuccess => Some(r"(?i)(successfully logged in|успешно авторизовались|успешно зарегистрировались)"),
+            Pattern::SessionRestored => Some(r"(?i)(session restored|уже авторизовались|сессия восстановлена)"),
+            Pattern::PortalJoined => None,
+            Pattern::BackInLobby => Some(r"(?i)(sent to the lobby|moved to the lobby|перемещены в лобби)"),
+            Pattern::Teleported => Some(r"(?i)(teleported|телепортированы)"),
+            Pattern::Muted => Some(r"(?i)(you are muted|вы замучены|у вас мут)"),
+            Pattern::Banned => Some(r"(?i)(you are banned|вы забанены)"),
+            Pattern::TooFast => Some(r"(?i)(too fast|slow down|не так быстро|слишком быстро|подождите перед)"),
+            Pattern::ChatGlobal | Pattern::ChatLocal | Pattern::ChatClan | Pattern::ChatPersonal => None,
+        }
+    }
+}
+
+/// A problem in a `patterns.toml` file.
+#[derive(Debug, Clone)]
+pub struct PatternIssue {
+    /// `section.name`, empty when the whole file is unreadable.
+    pub key: String,
+    pub message: String,
+}
+
+impl fmt::Display for PatternIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.key.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.key, self.message)
+        }
+    }
+}
+
+/// The compiled chat patterns of one server.
+#[derive(Debug, Default)]
+pub struct PatternCatalog {
+    patterns: HashMap<Pattern, Regex>,
+    /// The `patterns.toml` it was loaded from, `None` for the built-in catalog.
+    source: Option<PathBuf>,
+}
+
+impl PatternCatalog {
+    pub fn get(&self, pattern: Pattern) -> Option<&Regex> {
+        self.patterns.get(&pattern)
+    }
+
+    /// Whether `text` matches. A pattern the server does not define never matches.
+    pub fn is_match(&self, pattern: Pattern, text: &str) -> bool {
+        self.get(pattern).is_some_and(|regex| regex.is_match(text))
+    }
+
+    pub fn captures<'t>(&self, pattern: Pattern, text: &'t str) -> Option<Captures<'t>> {
+        self.get(pattern).and_then(|regex| regex.captures(text))
+    }
+
+    pub fn source(&self) -> Option<&Path> {
+        self.source.as_deref()
+    }
+
+    /// The built-in catalog with `overrides` on top.
+    #[cfg(test)]
+    pub(crate) fn with(overrides: &[(Pattern, &str)]) -> Self {
+        let mut catalog = Self::builtin();
+        for (pattern, regex) in overrides {
+            catalog.patterns.insert(*pattern, Regex::new(regex).unwrap());
+        }
+        catalog
+    }
+
+    fn builtin() -> Self {
+        let patterns = Pattern::ALL
+            .into_iter()
+            .filter_map(|pattern| Some((pattern, Regex::new(pattern.builtin()?).ok()?)))
+            .collect();
+        Self { patterns, source: None }
+    }
+
+    /// Compiles the built-in patterns overridden by `path`, reporting every problem.
+    fn compile(path: &Path) -> Result<Self, Vec<PatternIssue>> {
+        let file_issue = |message: String| vec![PatternIssue { key: String::new(), message }];
+        let content = std::fs::read_to_string(path)
+            .map_err(|err| file_issue(format!("Failed to read {}: {err}", path.display())))?;
+        let table: toml::Table = content.parse()
+            .map_err(|err| file_issue(format!("Failed to parse {}: {err}", path.display())))?;
+
+        let mut catalog = Self::builtin();
+        catalog.source = Some(path.to_path_buf());
+        let mut issues = Vec::new();
+        for (section, values) in &table {
+            let Some(values) = values.as_table() else {
+                issues.push(PatternIssue { key: section.clone(), message: "must be a table".to_string() });
+                continue;
+            };
+            for (name, value) in values {
+                let key = format!("{section}.{name}");
+                let Some(pattern) = Pattern::ALL.into_iter().find(|pattern| pattern.key() == key) else {
+                    issues.push(PatternIssue { key, message: "unknown pattern".to_string() });
+                    continue;
+                };
+                let Some(value) = value.as_str() else {
+                    issues.push(PatternIssue { key, message: "must be a string".to_string() });
+                    continue;
+                };
+                match Regex::new(value) {
+                    Ok(regex) if pattern.is_chat_format() => {
+                        let missing: Vec<_> = ["sender", "message"]
+                            .into_iter()
+                            .filter(|group| !regex.capture_names().flatten().any(|name| name == *group))
+                            .collect();
+                        if missing.is_empty() {
+                            catalog.patterns.insert(pattern, regex);
+                        } else {
+                            let message = format!("needs a named group for {}, e.g. (?P<{}>.+)", missing.join(" and "), missing[0]);
+                            issues.push(PatternIssue { key, message });
+                        }
+                    }
+                    Ok(regex) => {
+                        catalog.patterns.insert(pattern, regex);
+                    }
+                    Err(err) => issues.push(PatternIssue { key, message: err.to_string() }),
+                }
+            }
+        }
+
+        if issues.is_empty() { Ok(catalog) } else { Err(issues) }
+    }
+}
+
+type CachedCatalog = (Option<SystemTime>, Result<Arc<PatternCatalog>, Vec<PatternIssue>>);
+
+lazy_static! {
+    static ref BUILTIN: Arc<PatternCatalog> = Arc::new(PatternCatalog::builtin());
+    /// Compiled catalogs by file, so that the portals of a server share one.
+    static ref CATALOGS: Mutex<HashMap<PathBuf, CachedCatalog>> = Mutex::new(HashMap::new());
+}
+
+/// The pattern catalog of the server directory `server_dir`: its `patterns.toml` compiled
+/// over the built-in patterns, or the built-in ones alone when there is no such file.
+/// Compiled once per file and compiled again only when the file changes.
+pub fn load_patterns(server_dir: &Path) -> Result<Arc<PatternCatalog>, Vec<PatternIssue>> {
+    let path = server_dir.join(PATTERNS_FILE);
+    if !path.is_file() {
+        return Ok(BUILTIN.clone());
+    }
+
+    let modified = std::fs::metadata(&path).and_then(|meta| meta.modified()).ok();
+    let mut catalogs = CATALOGS.lock();
+    if let Some((cached_at, catalog)) = catalogs.get(&path)
+        && *cached_at == modified && modified.is_some()
+    {
+        return catalog.clone();
+    }
+    let catalog = PatternCatalog::compile(&path).map(Arc::new);
+    catalogs.insert(path, (modified, catalog.clone()));
+    catalog
+}
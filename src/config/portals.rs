@@ -0,0 +1,133 @@
+use super::Config;
+
+/// Placeholder replaced with the portal name in templated values.
+const PORTAL_PLACEHOLDER: &str = "{portal}";
+/// Placeholder replaced with the number the portal name ends with.
+const NUMBER_PLACEHOLDER: &str = "{n}";
+/// Portals a set may name at most, so that a typo such as `s1..s10000` fails loudly.
+const MAX_PORTALS: usize = 1000;
+
+/// Parses a portal set such as `"s1..s10"`, `"s1..s5, lobby"` or `"s1, s3"`.
+/// A range expands to every number between both ends, keeping their common prefix and
+/// their zero padding: `"s01..s10"` gives `s01`, `s02`, ... `s10`.
+pub fn parse_portal_set(set: &str) -> Result<Vec<String>, String> {
+    let mut portals = Vec::new();
+    for item in set.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+        match item.split_once("..") {
+            Some((start, end)) => {
+                let (prefix, from, from_digits) = split_number(start.trim())
+                    .ok_or_else(|| format!("range start {start:?} must end with a number"))?;
+                let (end_prefix, to, to_digits) = split_number(end.trim())
+                    .ok_or_else(|| format!("range end {end:?} must end with a number"))?;
+                if !end_prefix.is_empty() && end_prefix != prefix {
+                    return Err(format!("range {item:?} mixes prefixes {prefix:?} and {end_prefix:?}"));
+                }
+                if from > to {
+                    return Err(format!("range {item:?} is empty"));
+                }
+                if portals.len() + (to - from) as usize >= MAX_PORTALS {
+                    return Err(format!("range {item:?} names more than {MAX_PORTALS} portals"));
+                }
+                // Padded as soon as one end is written with a leading zero
+                let padded = [from_digits, to_digits].iter().any(|digits| digits.len() > 1 && digits.starts_with('0'));
+                let width = if padded { from_digits.len().max(to_digits.len()) } else { 0 };
+                portals.extend((from..=to).map(|n| format!("{prefix}{n:0width$}")));
+            }
+            None => portals.push(item.to_string()),
+        }
+    }
+    if portals.len() > MAX_PORTALS {
+        return Err(format!("{set:?} names more than {MAX_PORTALS} portals"));
+    }
+    if portals.is_empty() {
+        return Err(format!("{set:?} does not name any portal"));
+    }
+    Ok(portals)
+}
+
+/// Splits `"s07"` into its prefix, number and the digits as written.
+fn split_number(item: &str) -> Option<(&str, u32, &str)> {
+    let digits = item.len() - item.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    if digits == 0 {
+        return None;
+    }
+    let (prefix, number) = item.split_at(item.len() - digits);
+    number.parse().ok().map(|value| (prefix, value, number))
+}
+
+impl Config {
+    /// Portals declared by the `portals` set, or an empty list when there is none.
+    pub fn portal_set(&self) -> Result<Vec<String>, String> {
+        match &self.portals {
+            Some(set) => parse_portal_set(set),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Substitutes templated values: `{portal}` with the whole portal name and `{n}` with
+    /// the number it ends with, as written. For portal `s04`, `nickname = "Kemper{n}"` gives
+    /// `Kemper04` and `"Kemper_{portal}"` gives `Kemper_s04`. A name without a number keeps
+    /// `{n}`, which validation then reports.
+    pub fn apply_portal_template(&mut self, portal_name: &str) {
+        if let Some(nickname) = self.bot.as_mut().and_then(|bot| bot.nickname.as_mut()) {
+            *nickname = nickname.replace(PORTAL_PLACEHOLDER, portal_name);
+            if let Some((_, _, number)) = split_number(portal_name) {
+                *nickname = nickname.replace(NUMBER_PLACEHOLDER, number);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(set: &str) -> Vec<String> {
+        parse_portal_set(set).unwrap()
+    }
+
+    #[test]
+    fn expands_ranges_and_lists() {
+        assert_eq!(names("s1..s3"), ["s1", "s2", "s3"]);
+        assert_eq!(names("s8..10"), ["s8", "s9", "s10"]);
+        assert_eq!(names("s1..s2, lobby, s5"), ["s1", "s2", "lobby", "s5"]);
+        assert_eq!(names(" s1 ,, s3 "), ["s1", "s3"]);
+        assert_eq!(names("7..9"), ["7", "8", "9"]);
+    }
+
+    #[test]
+    fn keeps_zero_padding() {
+        assert_eq!(names("s01..s03"), ["s01", "s02", "s03"]);
+        assert_eq!(names("s08..s10"), ["s08", "s09", "s10"]);
+        assert_eq!(names("s001..s002"), ["s001", "s002"]);
+        assert_eq!(names("s9..s10"), ["s9", "s10"]);
+    }
+
+    #[test]
+    fn rejects_malformed_sets() {
+        for set in ["", " , ", "s..s3", "s1..s", "s1..p3", "s3..s1"] {
+            assert!(parse_portal_set(set).is_err(), "{set:?} should be rejected");
+        }
+    }
+
+    #[test]
+    fn caps_the_number_of_portals() {
+        assert_eq!(names("s1..s1000").len(), MAX_PORTALS);
+        assert!(parse_portal_set("s1..s1001").unwrap_err().contains("more than"));
+        assert!(parse_portal_set("s1..s4294967295").is_err());
+        assert!(parse_portal_set("lobby, s1..s1000").is_err());
+    }
+
+    #[test]
+    fn substitutes_the_portal_name_and_number() {
+        let nickname = |template: &str, portal| {
+            let mut config: Config = toml::from_str(&format!("[bot]\nnickname = \"{template}\"")).unwrap();
+            config.apply_portal_template(portal);
+            config.bot.unwrap().nickname.unwrap()
+        };
+        assert_eq!(nickname("Kemper_{portal}", "s4"), "Kemper_s4");
+        assert_eq!(nickname("Kemper{n}", "s4"), "Kemper4");
+        assert_eq!(nickname("Kemper{n}", "s04"), "Kemper04");
+        assert_eq!(nickname("Kemper{n}", "lobby"), "Kemper{n}");
+    }
+}
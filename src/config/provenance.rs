@@ -0,0 +1,162 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+
+use super::LoadedConfig;
+
+/// Keys whose values are never printed.
+const SECRET_KEYS: &[&str] = &["bot.password"];
+
+/// Where a resolved value came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    /// Not set in any file, the built-in default applies.
+    Default,
+    File(PathBuf),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// Records, for every dotted key (`delay.max.discord`), the last file that set it.
+#[derive(Debug, Clone, Default)]
+pub struct Provenance {
+    sources: BTreeMap<String, PathBuf>,
+    files: Vec<PathBuf>,
+}
+
+impl Provenance {
+    /// Records every leaf of `table` as coming from `path`. Layers must be recorded
+    /// in merge order so later files override earlier ones.
+    pub fn record(&mut self, path: &Path, table: &toml::Table) {
+        self.files.push(path.to_path_buf());
+        self.record_table("", path, table);
+    }
+
+    /// Every file of the chain in merge order, including ones that do not exist (yet).
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    fn record_table(&mut self, prefix: &str, path: &Path, table: &toml::Table) {
+        for (key, value) in table {
+            let key = join_key(prefix, key);
+            match value {
+                toml::Value::Table(inner) => self.record_table(&key, path, inner),
+                _ => {
+                    self.sources.insert(key, path.to_path_buf());
+                }
+            }
+        }
+    }
+
+    pub fn source(&self, key: &str) -> Source {
+        // A whole section comes from the file that set any of its keys
+        let section = format!("{key}.");
+        self.sources
+            .get(key)
+            .or_else(|| self.sources.iter().find(|(set, _)| set.starts_with(&section)).map(|(_, path)| path))
+            .map(|path| Source::File(path.clone()))
+            .unwrap_or(Source::Default)
+    }
+
+    /// Resolves a path-valued setting relative to the directory of the file that set it.
+    pub fn resolve_path(&self, key: &str, value: &str) -> PathBuf {
+        let value = Path::new(value);
+        match self.sources.get(key).and_then(|file| file.parent()) {
+            Some(dir) if value.is_relative() => dir.join(value),
+            _ => value.to_path_buf(),
+        }
+    }
+}
+
+/// Renders the resolved configuration with the source of every value next to it.
+pub fn explain(loaded: &LoadedConfig) -> String {
+    let mut out = String::new();
+
+    let chain: Vec<String> = loaded.provenance.files().iter().map(|path| path.display().to_string()).collect();
+    let _ = writeln!(out, "# chain: {}\n", chain.join(" -> "));
+
+    section(&mut out, "runtime", "", &loaded.runtime, |key| {
+        match key {
+            "bot.password" | "bot.password_source" => {
+                loaded.provenance.source(loaded.runtime.bot.password_source.key())
+            }
+            // The portal name and id are taken from the portal file path
+            "bot.portal" | "bot.id" => Source::File(loaded.portal_path.clone()),
+            // A missing min/max delay section falls back to the other one
+            _ if key.starts_with("delay.") => {
+                let source = loaded.provenance.source(key);
+                if source != Source::Default {
+                    return source;
+                }
+                let other = if key.starts_with("delay.min.") {
+                    key.replacen("delay.min.", "delay.max.", 1)
+                } else {
+                    key.replacen("delay.max.", "delay.min.", 1)
+                };
+                loaded.provenance.source(&other)
+            }
+            _ => loaded.provenance.source(key),
+        }
+    });
+    section(&mut out, "server", "server", &loaded.server, |key| loaded.provenance.source(key));
+    section(&mut out, "proxy", "proxy", &loaded.proxy, |key| loaded.provenance.source(key));
+
+    out
+}
+
+fn section<T: Serialize>(
+    out: &mut String,
+    title: &str,
+    prefix: &str,
+    value: &T,
+    source_of: impl Fn(&str) -> Source,
+) {
+    let mut leaves = Vec::new();
+    match serde_json::to_value(value) {
+        Ok(value) => flatten(prefix, &value, &mut leaves),
+        Err(err) => leaves.push((prefix.to_string(), format!("<unserializable: {err}>"))),
+    }
+
+    let width = leaves.iter().map(|(key, value)| key.len() + value.len()).max().unwrap_or(0);
+
+    let _ = writeln!(out, "[{title}]");
+    for (key, value) in leaves {
+        let source = source_of(&key);
+        let pad = width - key.len() - value.len();
+        let _ = writeln!(out, "{key} = {value}{:pad$}   # {source}", "");
+    }
+    out.push('\n');
+}
+
+fn flatten(prefix: &str, value: &serde_json::Value, leaves: &mut Vec<(String, String)>) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map {
+                flatten(&join_key(prefix, key), value, leaves);
+            }
+        }
+        serde_json::Value::Null => leaves.push((prefix.to_string(), "<unset>".to_string())),
+        _ if SECRET_KEYS.contains(&prefix) => leaves.push((prefix.to_string(), "\"********\"".to_string())),
+        _ => leaves.push((prefix.to_string(), value.to_string())),
+    }
+}
+
+fn join_key(prefix: &str, key: &str) -> String {
+    if prefix.is_empty() {
+        key.to_string()
+    } else {
+        format!("{prefix}.{key}")
+    }
+}
@@ -0,0 +1,207 @@
+use std::{fmt, time::Duration};
+
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::{ChatConfig, Merge};
+use crate::chat::{ChatChannel, ChatMessage};
+
+/// What a chat reaction does when it fires.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReactionAction {
+    /// Log the message.
+    Log,
+    /// Log the message as an alert.
+    Alert,
+    /// Send `text` to the chat, or run it when it starts with `/`. A text only becomes a
+    /// command when configured as one, never through its placeholders.
+    Say,
+    /// Send `text` to the sender in a private message.
+    Reply,
+}
+
+impl fmt::Display for ReactionAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ReactionAction::Log => "log",
+            ReactionAction::Alert => "alert",
+            ReactionAction::Say => "say",
+            ReactionAction::Reply => "reply",
+        };
+        f.write_str(name)
+    }
+}
+
+impl Merge for ReactionAction {
+    fn merge(&mut self, overlay: Self) {
+        *self = overlay;
+    }
+}
+
+impl Merge for ChatChannel {
+    fn merge(&mut self, overlay: Self) {
+        *self = overlay;
+    }
+}
+
+#[derive(Debug)]
+struct Reaction {
+    /// `None` for the `any` handler.
+    channel: Option<ChatChannel>,
+    pattern: Option<Regex>,
+    sender: Option<Regex>,
+    action: ReactionAction,
+    text: String,
+    cooldown: Duration,
+}
+
+/// A reaction that fired, with the placeholders of its text filled in.
+#[derive(Debug, Clone)]
+pub struct Fired {
+    pub action: ReactionAction,
+    pub text: String,
+    /// Whether the configured text is a command, i.e. starts with `/`.
+    pub command: bool,
+    /// Number of the rule in `chat.reactions`, from 1.
+    pub rule: usize,
+}
+
+impl Fired {
+    /// Whether a placeholder turned the text into a command, e.g. `{message}` filled in
+    /// with `/op Griefer`. Such a text must not be said.
+    pub fn is_injected_command(&self) -> bool {
+        !self.command && self.text.trim_start().starts_with('/')
+    }
+}
+
+/// The compiled `chat.reactions` of a portal.
+#[derive(Debug, Default)]
+pub struct Reactions {
+    rules: Vec<Reaction>,
+}
+
+impl Reactions {
+    /// Compiles `chat.reactions`, failing on the first reaction whose regex or cooldown
+    /// does not parse; `check` lists all of them.
+    pub fn compile(config: Option<&ChatConfig>) -> Result<Self, String> {
+        let config = config.cloned().unwrap_or_default();
+        let regex = |number: usize, value: &Option<String>| {
+            value.as_deref().map(Regex::new).transpose().map_err(|err| format!("reaction {number}: {err}"))
+        };
+
+        let mut rules = Vec::new();
+        for (index, reaction) in config.reactions.iter().flatten().enumerate() {
+            let number = index + 1;
+            let cooldown = match &reaction.cooldown {
+                Some(cooldown) => cooldown.to_cooldown().map_err(|err| format!("reaction {number}: cooldown {err}"))?,
+                None => Duration::ZERO,
+            };
+            rules.push(Reaction {
+                channel: reaction.channel,
+                pattern: regex(number, &reaction.pattern)?,
+                sender: regex(number, &reaction.sender)?,
+                action: reaction.action.ok_or_else(|| format!("reaction {number}: action is missing"))?,
+                text: reaction.text.clone().unwrap_or_default(),
+                cooldown,
+            });
+        }
+        Ok(Self { rules })
+    }
+
+    /// Fires every reaction of `channel` (`None` for those without one) that matches
+    /// `message`, in config order. `cooling_down(name, cooldown)` tells whether a reaction
+    /// has to wait, marking it used otherwise; reactions are named `reaction:<number>`.
+    pub fn fire(
+        &self,
+        channel: Option<ChatChannel>,
+        message: &ChatMessage,
+        mut cooling_down: impl FnMut(&str, Duration) -> bool,
+    ) -> Vec<Fired> {
+        let sender = message.sender.as_deref().unwrap_or_default();
+        let mut fired = Vec::new();
+        for (index, rule) in self.rules.iter().enumerate() {
+            if rule.channel != channel
+                || rule.pattern.as_ref().is_some_and(|pattern| !pattern.is_match(&message.body))
+                || rule.sender.as_ref().is_some_and(|pattern| message.sender.is_none() || !pattern.is_match(sender))
+            {
+                continue;
+            }
+            if cooling_down(&format!("reaction:{}", index + 1), rule.cooldown) {
+                continue;
+            }
+            fired.push(Fired {
+                action: rule.action,
+                text: rule.text.replace("{sender}", sender).replace("{message}", &message.body),
+                command: rule.text.trim_start().starts_with('/'),
+                rule: index + 1,
+            });
+        }
+        fired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::RefCell, collections::HashSet};
+    use crate::chat::tests::{line, GLOBAL};
+    use crate::config::{ChatReaction, ConfigDuration, Pattern, PatternCatalog};
+
+    fn reactions(rules: Vec<ChatReaction>) -> Reactions {
+        Reactions::compile(Some(&ChatConfig { reactions: Some(rules) })).unwrap()
+    }
+
+    fn say(text: &str) -> ChatReaction {
+        ChatReaction {
+            channel: Some(ChatChannel::Global),
+            action: Some(ReactionAction::Say),
+            text: Some(text.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn placeholders_are_filled_in() {
+        let patterns = PatternCatalog::with(&[(Pattern::ChatGlobal, GLOBAL)]);
+        let fired = reactions(vec![say("hi {sender}, you said {message}")])
+            .fire(Some(ChatChannel::Global), &line("[G] Steve: hello", &patterns), |_, _| false);
+        assert_eq!(fired.len(), 1);
+        assert_eq!(fired[0].text, "hi Steve, you said hello");
+        assert!(!fired[0].is_injected_command());
+    }
+
+    #[test]
+    fn placeholders_cannot_make_a_command() {
+        let patterns = PatternCatalog::with(&[(Pattern::ChatGlobal, GLOBAL)]);
+        let message = line("[G] Griefer: /op Griefer", &patterns);
+        let fired = reactions(vec![say("{message}"), say("/msg {sender} welcome back")])
+            .fire(Some(ChatChannel::Global), &message, |_, _| false);
+        assert_eq!(fired[0].text, "/op Griefer");
+        assert!(fired[0].is_injected_command());
+        assert!(!fired[1].is_injected_command());
+    }
+
+    #[test]
+    fn filters_and_cooldowns_apply() {
+        let patterns = PatternCatalog::with(&[(Pattern::ChatGlobal, GLOBAL)]);
+        let reactions = reactions(vec![ChatReaction {
+            pattern: Some("(?i)shop".to_string()),
+            sender: Some("^Steve$".to_string()),
+            cooldown: Some(ConfigDuration::Text("1h".to_string())),
+            ..say("/warp shop")
+        }]);
+        let used = RefCell::new(HashSet::new());
+        let cooling_down = |name: &str, cooldown: Duration| {
+            assert_eq!(cooldown, Duration::from_secs(3600));
+            !used.borrow_mut().insert(name.to_string())
+        };
+        let fire = |text: &str, channel| reactions.fire(Some(channel), &line(text, &patterns), cooling_down).len();
+
+        assert_eq!(fire("[G] Alex: where is the shop", ChatChannel::Global), 0);
+        assert_eq!(fire("[G] Steve: where is the shop", ChatChannel::Local), 0);
+        assert_eq!(fire("[G] Steve: where is the shop", ChatChannel::Global), 1);
+        assert_eq!(fire("[G] Steve: where is the shop", ChatChannel::Global), 0);
+    }
+}
@@ -0,0 +1,208 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context, Result};
+use argon2::Argon2;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng},
+    ChaCha20Poly1305,
+    Key,
+    Nonce,
+};
+use serde::{Deserialize, Serialize, Serializer};
+
+use super::{BotConfig, Provenance, SecretsConfig};
+
+/// Environment variable holding the secrets file passphrase unless `secrets.passphrase_env` says otherwise.
+pub const DEFAULT_PASSPHRASE_ENV: &str = "MRSBOT_SECRETS_PASSPHRASE";
+
+/// Where the bot password was taken from. Never carries the password itself.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum PasswordSource {
+    /// `bot.password` written in the config tree.
+    #[default]
+    Inline,
+    Env(String),
+    File(PathBuf),
+    Secret { file: PathBuf, name: String },
+}
+
+impl PasswordSource {
+    /// Config key that selected this source.
+    pub fn key(&self) -> &'static str {
+        match self {
+            PasswordSource::Inline => "bot.password",
+            PasswordSource::Env(_) => "bot.password_env",
+            PasswordSource::File(_) => "bot.password_file",
+            PasswordSource::Secret { .. } => "bot.password_secret",
+        }
+    }
+}
+
+impl fmt::Display for PasswordSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PasswordSource::Inline => write!(f, "config value bot.password"),
+            PasswordSource::Env(var) => write!(f, "environment variable {var}"),
+            PasswordSource::File(path) => write!(f, "file {}", path.display()),
+            PasswordSource::Secret { file, name } => write!(f, "secret '{}' in {}", name, file.display()),
+        }
+    }
+}
+
+impl Serialize for PasswordSource {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Resolves the bot password from whichever source the config selects.
+/// `validate` guarantees that exactly one source is set.
+pub(super) fn resolve_password(
+    bot: &BotConfig,
+    secrets: Option<&SecretsConfig>,
+    provenance: &Provenance,
+) -> Result<(String, PasswordSource)> {
+    if let Some(password) = &bot.password {
+        return Ok((password.clone(), PasswordSource::Inline));
+    }
+
+    if let Some(var) = &bot.password_env {
+        let password = std::env::var(var)
+            .context(format!("Environment variable {var} with the bot password is not set"))?;
+        return Ok((password, PasswordSource::Env(var.clone())));
+    }
+
+    if let Some(file) = &bot.password_file {
+        let path = provenance.resolve_path("bot.password_file", file);
+        let password = std::fs::read_to_string(&path)
+            .context(format!("Failed to read password file: {}", path.display()))?;
+        let password = password.trim_end_matches(['\r', '\n']).to_string();
+        return Ok((password, PasswordSource::File(path)));
+    }
+
+    if let Some(name) = &bot.password_secret {
+        let file = secrets
+            .and_then(|secrets| secrets.file.as_ref())
+            .ok_or_else(|| anyhow!("bot.password_secret is set but secrets.file is missing"))?;
+        let path = provenance.resolve_path("secrets.file", file);
+        let passphrase_env = secrets
+            .and_then(|secrets| secrets.passphrase_env.as_deref())
+            .unwrap_or(DEFAULT_PASSPHRASE_ENV);
+        let passphrase = std::env::var(passphrase_env)
+            .context(format!("Environment variable {passphrase_env} with the secrets passphrase is not set"))?;
+        let password = read_secret(&path, &passphrase, name)?;
+        return Ok((password, PasswordSource::Secret { file: path, name: name.clone() }));
+    }
+
+    Err(anyhow!("Bot password is missing"))
+}
+
+/// On-disk layout of an encrypted secrets file. The plaintext is a flat TOML
+/// table of `name = "secret"` pairs, encrypted with ChaCha20-Poly1305 under a key
+/// derived from the passphrase with Argon2id.
+#[derive(Debug, Deserialize, Serialize)]
+struct SealedSecrets {
+    salt: String,
+    nonce: String,
+    data: String,
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Key> {
+    let mut key = Key::default();
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|err| anyhow!("Failed to derive secrets key: {err}"))?;
+    Ok(key)
+}
+
+/// Decrypts the secrets file at `path` and returns the secret stored under `name`.
+pub fn read_secret(path: &Path, passphrase: &str, name: &str) -> Result<String> {
+    let content = std::fs::read_to_string(path)
+        .context(format!("Failed to read secrets file: {}", path.display()))?;
+    let sealed: SealedSecrets = toml::from_str(&content)
+        .context(format!("Failed to parse secrets file: {}", path.display()))?;
+
+    let salt = STANDARD.decode(&sealed.salt).context("Invalid salt in secrets file")?;
+    let nonce = STANDARD.decode(&sealed.nonce).context("Invalid nonce in secrets file")?;
+    let data = STANDARD.decode(&sealed.data).context("Invalid data in secrets file")?;
+    if nonce.len() != 12 {
+        return Err(anyhow!("Invalid nonce length in secrets file: {}", path.display()));
+    }
+
+    let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, &salt)?);
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(&nonce), data.as_ref())
+        .map_err(|_| anyhow!("Failed to decrypt {} (wrong passphrase?)", path.display()))?;
+    let secrets: toml::Table = String::from_utf8(plaintext)
+        .context("Secrets file is not valid UTF-8")?
+        .parse()
+        .context("Decrypted secrets are not a valid TOML table")?;
+
+    match secrets.get(name) {
+        Some(toml::Value::String(secret)) => Ok(secret.clone()),
+        Some(_) => Err(anyhow!("Secret '{}' in {} is not a string", name, path.display())),
+        None => Err(anyhow!("Secret '{}' not found in {}", name, path.display())),
+    }
+}
+
+/// Encrypts a plaintext TOML table of secrets from `input` into `output`.
+pub fn seal_secrets(input: &Path, output: &Path, passphrase: &str) -> Result<()> {
+    let plaintext = std::fs::read_to_string(input)
+        .context(format!("Failed to read plaintext secrets: {}", input.display()))?;
+    plaintext.parse::<toml::Table>()
+        .context(format!("Plaintext secrets are not a valid TOML table: {}", input.display()))?;
+
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, &salt)?);
+    let data = cipher
+        .encrypt(&nonce, plaintext.as_bytes())
+        .map_err(|_| anyhow!("Failed to encrypt secrets"))?;
+
+    let sealed = SealedSecrets {
+        salt: STANDARD.encode(salt),
+        nonce: STANDARD.encode(nonce),
+        data: STANDARD.encode(data),
+    };
+    std::fs::write(output, toml::to_string(&sealed)?)
+        .context(format!("Failed to write secrets file: {}", output.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::scratch_dir;
+
+    #[test]
+    fn sealed_secrets_round_trip() {
+        let dir = scratch_dir("secrets");
+        let (plain, sealed) = (dir.join("secrets.toml"), dir.join("secrets.enc"));
+        std::fs::write(&plain, "kemper = \"hunter2\"\nother = \"x\"\n").unwrap();
+        seal_secrets(&plain, &sealed, "passphrase").unwrap();
+
+        let content = std::fs::read_to_string(&sealed).unwrap();
+        assert!(!content.contains("hunter2"));
+        assert_eq!(read_secret(&sealed, "passphrase", "kemper").unwrap(), "hunter2");
+        assert!(read_secret(&sealed, "passphrase", "missing").unwrap_err().to_string().contains("not found"));
+        assert!(read_secret(&sealed, "wrong", "kemper").unwrap_err().to_string().contains("wrong passphrase"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_to_seal_invalid_toml() {
+        let dir = scratch_dir("secrets-invalid");
+        let plain = dir.join("secrets.toml");
+        std::fs::write(&plain, "not toml").unwrap();
+        assert!(seal_secrets(&plain, &dir.join("secrets.enc"), "passphrase").is_err());
+        assert!(!dir.join("secrets.enc").exists());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,581 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use regex::Regex;
+
+use super::{
+    discover_portals, format_duration, load_patterns, merge_cfg, Config, ConfigDuration, Delay, Provenance, ReactionAction,
+    Source, DEFAULT_CONFIRM_DISTANCE, DEFAULT_WARP_DISTANCE, PATTERNS_FILE,
+};
+use crate::{
+    connect::{parse_address, parse_name_server},
+    re::{NICKNAME, UUID},
+};
+
+/// A single problem found in a portal's merged configuration.
+#[derive(Debug, Clone)]
+pub struct ConfigIssue {
+    /// File the offending value came from, or the portal file for missing keys.
+    pub source: Source,
+    /// Dotted key path, e.g. `delay.min.global`.
+    pub key: String,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.key.is_empty() {
+            write!(f, "{}: {}", self.source, self.message)
+        } else {
+            write!(f, "{}: {}: {}", self.source, self.key, self.message)
+        }
+    }
+}
+
+/// Every problem found while validating one portal, reported at once.
+#[derive(Debug, Clone)]
+pub struct ConfigErrors(pub Vec<ConfigIssue>);
+
+impl fmt::Display for ConfigErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} config problem(s):", self.0.len())?;
+        for issue in &self.0 {
+            write!(f, "\n  {issue}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigErrors {}
+
+struct Validator<'a> {
+    provenance: &'a Provenance,
+    portal_path: &'a Path,
+    issues: Vec<ConfigIssue>,
+}
+
+impl Validator<'_> {
+    /// Reports a problem with a value that is set somewhere in the chain.
+    fn invalid(&mut self, key: &str, message: impl Into<String>) {
+        self.issues.push(ConfigIssue {
+            source: self.provenance.source(key),
+            key: key.to_string(),
+            message: message.into(),
+        });
+    }
+
+    /// Reports a key that no file in the chain sets.
+    fn missing(&mut self, key: &str) {
+        self.issues.push(ConfigIssue {
+            source: Source::File(self.portal_path.to_path_buf()),
+            key: key.to_string(),
+            message: "missing".to_string(),
+        });
+    }
+
+    /// Parses a duration value, reporting it if it is malformed, negative or zero.
+    fn duration(&mut self, key: &str, value: &ConfigDuration) -> Option<Duration> {
+        value.to_duration().map_err(|err| self.invalid(key, err)).ok()
+    }
+
+    /// Parses a cooldown value, reporting it if it is malformed or negative.
+    fn cooldown(&mut self, key: &str, value: &ConfigDuration) -> Option<Duration> {
+        value.to_cooldown().map_err(|err| self.invalid(key, err)).ok()
+    }
+
+    fn require<'v, T>(&mut self, key: &str, value: Option<&'v T>) -> Option<&'v T> {
+        if value.is_none() {
+            self.missing(key);
+        }
+        value
+    }
+}
+
+impl Config {
+    /// Checks the merged config for missing keys and semantic problems, collecting all
+    /// of them instead of stopping at the first one.
+    pub fn validate(&self, provenance: &Provenance, portal_path: &Path) -> Vec<ConfigIssue> {
+        let mut v = Validator { provenance, portal_path, issues: Vec::new() };
+
+        if let Err(err) = self.portal_set() {
+            v.invalid("portals", err);
+        }
+
+        let bot = self.bot.clone().unwrap_or_default();
+        if let Some(nickname) = v.require("bot.nickname", bot.nickname.as_ref())
+            && !NICKNAME.is_match(nickname)
+        {
+            v.invalid("bot.nickname", format!("{nickname:?} must be 3-16 characters of A-Z, a-z, 0-9 or _"));
+        }
+        let password_keys: Vec<&str> = [
+            ("bot.password", bot.password.is_some()),
+            ("bot.password_env", bot.password_env.is_some()),
+            ("bot.password_file", bot.password_file.is_some()),
+            ("bot.password_secret", bot.password_secret.is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(key, set)| set.then_some(key))
+        .collect();
+        match password_keys.as_slice() {
+            [] => v.missing("bot.password"),
+            [_] => {}
+            [first, rest @ ..] => {
+                v.invalid(first, format!("only one password source may be set in a file, also found {}", rest.join(", ")));
+            }
+        }
+        if bot.password_secret.is_some() && self.secrets.as_ref().and_then(|s| s.file.as_ref()).is_none() {
+            v.missing("secrets.file");
+        }
+        if let Some(warp) = v.require("bot.warp", bot.warp.as_ref())
+            && warp.trim().is_empty()
+        {
+            v.invalid("bot.warp", "must not be empty");
+        }
+
+        let server = self.server.clone().unwrap_or_default();
+        match (&server.host, &server.addresses) {
+            (None, None) => v.missing("server.host"),
+            (Some(host), _) if host.trim().is_empty() => v.invalid("server.host", "must not be empty"),
+            _ => {}
+        }
+        if server.port == Some(0) {
+            v.invalid("server.port", "must be between 1 and 65535");
+        }
+        for address in server.addresses.iter().flatten() {
+            if let Err(err) = parse_address(address) {
+                v.invalid("server.addresses", err);
+            }
+        }
+        if let Some(timeout) = &server.timeout {
+            v.duration("server.timeout", timeout);
+        }
+        if let Some(dns) = &server.dns
+            && parse_name_server(dns).is_err()
+        {
+            v.invalid("server.dns", format!("{dns:?} is not an ip[:port] address"));
+        }
+
+        if let Some(proxy) = &self.proxy {
+            match (&proxy.host, proxy.port) {
+                (Some(_), None) => v.missing("proxy.port"),
+                (None, Some(_)) => v.missing("proxy.host"),
+                _ => {}
+            }
+            if proxy.port == Some(0) {
+                v.invalid("proxy.port", "must be between 1 and 65535");
+            }
+        }
+
+        if let Some(join) = &self.join {
+            for (key, value) in [
+                ("join.gap", &join.gap),
+                ("join.proxy_gap", &join.proxy_gap),
+                ("join.auth_timeout", &join.auth_timeout),
+            ] {
+                if let Some(value) = value {
+                    v.duration(key, value);
+                }
+            }
+            if join.max_authenticating == Some(0) {
+                v.invalid("join.max_authenticating", "must be at least 1");
+            }
+        }
+
+        if let Some(auth) = &self.auth {
+            if let Some(timeout) = &auth.timeout {
+                v.duration("auth.timeout", timeout);
+            }
+            if auth.max_attempts == Some(0) {
+                v.invalid("auth.max_attempts", "must be at least 1");
+            }
+        }
+
+        if let Some(navigation) = &self.navigation {
+            if let Some(timeout) = &navigation.timeout {
+                v.duration("navigation.timeout", timeout);
+            }
+            if navigation.max_attempts == Some(0) {
+                v.invalid("navigation.max_attempts", "must be at least 1");
+            }
+            for (key, distance) in [
+                ("navigation.confirm_distance", navigation.confirm_distance),
+                ("navigation.warp_distance", navigation.warp_distance),
+            ] {
+                if let Some(distance) = distance
+                    && (distance.is_nan() || distance <= 0.0)
+                {
+                    v.invalid(key, format!("{distance} must be positive"));
+                }
+            }
+            // A warp back is confirmed within `confirm_distance` of the warp, which has to be
+            // closer than where it is triggered
+            let confirm = navigation.confirm_distance.unwrap_or(DEFAULT_CONFIRM_DISTANCE);
+            let warp = navigation.warp_distance.unwrap_or(DEFAULT_WARP_DISTANCE);
+            if confirm > warp {
+                let key = match navigation.confirm_distance {
+                    Some(_) => "navigation.confirm_distance",
+                    None => "navigation.warp_distance",
+                };
+                v.invalid(key, format!(
+                    "navigation.confirm_distance = {confirm} is bigger than navigation.warp_distance = {warp}"
+                ));
+            }
+            if let Some(cooldown) = &navigation.warp_cooldown {
+                v.duration("navigation.warp_cooldown", cooldown);
+            }
+        }
+
+        if let Some(restart) = &self.restart {
+            let initial = restart.initial_delay.as_ref().and_then(|value| v.duration("restart.initial_delay", value));
+            let max = restart.max_delay.as_ref().and_then(|value| v.duration("restart.max_delay", value));
+            if let (Some(initial), Some(max)) = (initial, max)
+                && initial > max
+            {
+                v.invalid("restart.initial_delay", format!(
+                    "{} is bigger than restart.max_delay = {}",
+                    format_duration(initial),
+                    format_duration(max),
+                ));
+            }
+            for (key, value) in [("restart.park_for", &restart.park_for), ("restart.stable_after", &restart.stable_after)] {
+                if let Some(value) = value {
+                    v.duration(key, value);
+                }
+            }
+            if let Some(jitter) = restart.jitter
+                && !(0.0..=1.0).contains(&jitter)
+            {
+                v.invalid("restart.jitter", format!("{jitter} must be between 0 and 1"));
+            }
+        }
+
+        if let Some(kick) = &self.kick {
+            if let Some(delay) = &kick.delay {
+                v.duration("kick.delay", delay);
+            }
+            // Rules are one array value, so issues point at `kick.rules` and name the rule
+            for (index, rule) in kick.rules.iter().flatten().enumerate() {
+                let number = index + 1;
+                match &rule.pattern {
+                    Some(pattern) => {
+                        if let Err(err) = Regex::new(pattern) {
+                            v.invalid("kick.rules", format!("rule {number}: {err}"));
+                        }
+                    }
+                    None => v.invalid("kick.rules", format!("rule {number}: pattern is missing")),
+                }
+                if rule.category.is_none() {
+                    v.invalid("kick.rules", format!("rule {number}: category is missing"));
+                }
+                if let Some(delay) = &rule.delay
+                    && let Err(err) = delay.to_duration()
+                {
+                    v.invalid("kick.rules", format!("rule {number}: delay {err}"));
+                }
+            }
+        }
+
+        if let Some(chat) = &self.chat {
+            for (index, reaction) in chat.reactions.iter().flatten().enumerate() {
+                let number = index + 1;
+                for (name, value) in [("pattern", &reaction.pattern), ("sender", &reaction.sender)] {
+                    if let Some(value) = value
+                        && let Err(err) = Regex::new(value)
+                    {
+                        v.invalid("chat.reactions", format!("reaction {number}: {name} {err}"));
+                    }
+                }
+                match reaction.action {
+                    None => v.invalid("chat.reactions", format!("reaction {number}: action is missing")),
+                    Some(action @ (ReactionAction::Say | ReactionAction::Reply)) if reaction.text.is_none() => {
+                        v.invalid("chat.reactions", format!("reaction {number}: {action} needs a text"));
+                    }
+                    _ => {}
+                }
+                if let Some(cooldown) = &reaction.cooldown
+                    && let Err(err) = cooldown.to_cooldown()
+                {
+                    v.invalid("chat.reactions", format!("reaction {number}: cooldown {err}"));
+                }
+            }
+        }
+
+        if let Some(commands) = &self.commands {
+            if let Some(cooldown) = &commands.cooldown {
+                v.cooldown("commands.cooldown", cooldown);
+            }
+            for (name, cooldown) in commands.cooldowns.iter().flatten() {
+                let key = format!("commands.cooldowns.{name}");
+                if crate::commands::find(name).is_none() {
+                    v.invalid(&key, "unknown command");
+                } else {
+                    v.cooldown(&key, cooldown);
+                }
+            }
+            for owner in commands.owners.iter().flatten() {
+                if !NICKNAME.is_match(owner) {
+                    v.invalid("commands.owners", format!("{owner:?} is not a nickname"));
+                }
+            }
+            for (name, role) in commands.roles.iter().flatten() {
+                if name == crate::commands::OWNER_ROLE {
+                    v.invalid(&format!("commands.roles.{name}"), "is reserved for commands.owners");
+                }
+                for command in role.commands.iter().flatten() {
+                    if command != "*" && crate::commands::find(command).is_none() {
+                        v.invalid(&format!("commands.roles.{name}.commands"), format!("unknown command {command:?}"));
+                    }
+                }
+                for player in role.players.iter().flatten() {
+                    if !NICKNAME.is_match(player) {
+                        v.invalid(&format!("commands.roles.{name}.players"), format!("{player:?} is not a nickname"));
+                    }
+                }
+                for uuid in role.uuids.iter().flatten() {
+                    if !UUID.is_match(uuid) {
+                        v.invalid(&format!("commands.roles.{name}.uuids"), format!("{uuid:?} is not a UUID"));
+                    }
+                }
+            }
+        }
+
+        if let Some(outbox) = &self.outbox {
+            for (key, value) in [
+                ("outbox.min_interval", &outbox.min_interval),
+                ("outbox.refill", &outbox.refill),
+                ("outbox.recover_after", &outbox.recover_after),
+            ] {
+                if let Some(value) = value {
+                    v.duration(key, value);
+                }
+            }
+            if outbox.burst == Some(0) {
+                v.invalid("outbox.burst", "must be at least 1");
+            }
+            if outbox.max_queued == Some(0) {
+                v.invalid("outbox.max_queued", "must be at least 1");
+            }
+            if let Some(slowdown) = outbox.max_slowdown
+                && (slowdown.is_nan() || slowdown < 1.0)
+            {
+                v.invalid("outbox.max_slowdown", format!("{slowdown} must be at least 1"));
+            }
+        }
+
+        // The server's pattern catalog sits next to all.toml
+        let server_dir = portal_path.parent().unwrap_or(Path::new(""));
+        if let Err(issues) = load_patterns(server_dir) {
+            let source = Source::File(server_dir.join(PATTERNS_FILE));
+            v.issues.extend(issues.into_iter().map(|issue| ConfigIssue {
+                source: source.clone(),
+                key: issue.key,
+                message: issue.message,
+            }));
+        }
+
+        let delay = self.delay.clone().unwrap_or_default();
+        // A missing min or max section falls back to the other one, see `resolve`
+        let (min_section, max_section) = match (&delay.min, &delay.max) {
+            (None, None) => {
+                v.missing("delay.min");
+                v.missing("delay.max");
+                return v.issues;
+            }
+            (Some(_), None) => ("delay.min", "delay.min"),
+            (None, Some(_)) => ("delay.max", "delay.max"),
+            (Some(_), Some(_)) => ("delay.min", "delay.max"),
+        };
+        let min = delay.min.as_ref().or(delay.max.as_ref()).cloned().unwrap_or_default();
+        let max = delay.max.as_ref().or(delay.min.as_ref()).cloned().unwrap_or_default();
+        for (name, pick) in DELAY_FIELDS {
+            let min_key = format!("{min_section}.{name}");
+            let max_key = format!("{max_section}.{name}");
+            let min_value = v.require(&min_key, pick(&min)).and_then(|value| v.duration(&min_key, value));
+            let max_value = v.require(&max_key, pick(&max)).and_then(|value| v.duration(&max_key, value));
+
+            if let (Some(min_value), Some(max_value)) = (min_value, max_value)
+                && min_value > max_value
+            {
+                v.invalid(&min_key, format!(
+                    "{} is bigger than {} = {}",
+                    format_duration(min_value),
+                    max_key,
+                    format_duration(max_value),
+                ));
+            }
+        }
+
+        // With a single delay section both sides report the same key
+        v.issues.dedup_by(|a, b| a.key == b.key && a.message == b.message);
+        v.issues
+    }
+}
+
+type DelayField = fn(&Delay) -> Option<&ConfigDuration>;
+
+const DELAY_FIELDS: [(&str, DelayField); 3] = [
+    ("global", |delay| delay.global.as_ref()),
+    ("discord", |delay| delay.discord.as_ref()),
+    ("invite", |delay| delay.invite.as_ref()),
+];
+
+/// Validates every portal found under `root` and prints a report.
+/// Returns an error if any portal has problems.
+pub fn check_tree(root: &Path) -> Result<()> {
+    let portals = discover_portals(root)?;
+    if portals.is_empty() {
+        return Err(anyhow!("No portal configs found in {}", root.display()));
+    }
+
+    // Only merge and validate: secrets are resolved at startup on the host that runs the bot
+    let mut failed: Vec<PathBuf> = Vec::new();
+    for portal in &portals {
+        let issues = match merge_cfg(portal) {
+            Ok((config, provenance)) => config.validate(&provenance, portal),
+            Err(err) => {
+                println!("FAIL  {}", portal.display());
+                println!("      {err:#}");
+                failed.push(portal.clone());
+                continue;
+            }
+        };
+
+        if issues.is_empty() {
+            println!("ok    {}", portal.display());
+        } else {
+            println!("FAIL  {}", portal.display());
+            issues.iter().for_each(|issue| println!("      {issue}"));
+            failed.push(portal.clone());
+        }
+    }
+
+    println!("\nChecked {} portal(s), {} with problems", portals.len(), failed.len());
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!("{} portal config(s) failed validation", failed.len()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::config::Merge;
+
+    const VALID: &str = r#"
+        [bot]
+        nickname = "Kemper1"
+        password = "secret"
+        warp = "shop"
+
+        [server]
+        host = "mc.example.com"
+
+        [delay.min]
+        global = 60
+        discord = "2m"
+        invite = "5m"
+
+        [delay.max]
+        global = 120
+        discord = "4m"
+        invite = "10m"
+    "#;
+
+    fn validate(content: &str) -> Vec<ConfigIssue> {
+        validate_layers(&[("srv/all.toml", content)])
+    }
+
+    /// Merges `(path, content)` layers in order, like `merge_cfg` does.
+    fn validate_layers(layers: &[(&str, &str)]) -> Vec<ConfigIssue> {
+        let mut config = Config::default();
+        let mut provenance = Provenance::default();
+        for (path, content) in layers {
+            config.merge(toml::from_str(content).unwrap());
+            provenance.record(Path::new(path), &content.parse().unwrap());
+        }
+        config.validate(&provenance, Path::new("srv/s1.toml"))
+    }
+
+    fn keys(issues: &[ConfigIssue]) -> Vec<&str> {
+        issues.iter().map(|issue| issue.key.as_str()).collect()
+    }
+
+    #[test]
+    fn valid_config_has_no_issues() {
+        assert!(validate(VALID).is_empty(), "{:?}", validate(VALID));
+    }
+
+    #[test]
+    fn collects_every_missing_key() {
+        let issues = validate(&VALID.replace("nickname = \"Kemper1\"", "").replace("warp = \"shop\"", ""));
+        assert_eq!(keys(&issues), ["bot.nickname", "bot.warp"]);
+        assert!(issues.iter().all(|issue| issue.message == "missing"));
+        assert!(issues.iter().all(|issue| issue.source == Source::File("srv/s1.toml".into())));
+    }
+
+    #[test]
+    fn reports_invalid_values_at_the_file_that_set_them() {
+        let issues = validate(&VALID.replace("Kemper1", "no spaces allowed").replace("global = 60", "global = 600"));
+        assert_eq!(keys(&issues), ["bot.nickname", "delay.min.global"]);
+        assert!(issues.iter().all(|issue| issue.source == Source::File("srv/all.toml".into())));
+        assert!(issues[1].message.contains("bigger than delay.max.global"), "{}", issues[1].message);
+    }
+
+    #[test]
+    fn confirm_distance_stays_within_warp_distance() {
+        let issues = validate(&format!("{VALID}\n[navigation]\nconfirm_distance = 8.0"));
+        assert_eq!(keys(&issues), ["navigation.confirm_distance"]);
+
+        let issues = validate(&format!("{VALID}\n[navigation]\nwarp_distance = 2.0"));
+        assert_eq!(keys(&issues), ["navigation.warp_distance"]);
+
+        assert!(validate(&format!("{VALID}\n[navigation]\nconfirm_distance = 8.0\nwarp_distance = 8.0")).is_empty());
+    }
+
+    #[test]
+    fn single_delay_section_is_used_for_both_sides() {
+        let content = VALID.split("[delay.max]").next().unwrap();
+        assert!(validate(content).is_empty());
+
+        let issues = validate(&content.replace("global = 60", "global = 0"));
+        assert_eq!(keys(&issues), ["delay.min.global"]);
+    }
+
+    #[test]
+    fn rejects_malformed_durations_and_ranges() {
+        let content = format!("{VALID}\n[restart]\ninitial_delay = \"10x\"\njitter = 2.0\n\n[join]\nmax_authenticating = 0\n");
+        let issues = validate(&content);
+        assert_eq!(keys(&issues), ["join.max_authenticating", "restart.initial_delay", "restart.jitter"]);
+    }
+
+    #[test]
+    fn rejects_incomplete_proxies() {
+        let issues = validate(&format!("{VALID}\n[proxy]\nport = 0\n"));
+        assert_eq!(keys(&issues), ["proxy.host", "proxy.port"]);
+    }
+
+    #[test]
+    fn more_specific_layer_replaces_the_password_source() {
+        let default = "[bot]\npassword_env = \"MRSBOT_PASSWORD\"\n";
+        assert!(validate_layers(&[("default.toml", default), ("srv/all.toml", VALID)]).is_empty());
+
+        let from_file = VALID.replace("password = \"secret\"", "password_file = \"password.txt\"");
+        assert!(validate_layers(&[("default.toml", default), ("srv/all.toml", &from_file)]).is_empty());
+    }
+
+    #[test]
+    fn rejects_two_password_sources_in_one_file() {
+        let both = VALID.replace("password = \"secret\"", "password = \"secret\"\npassword_env = \"PASSWORD\"");
+        let issues = validate_layers(&[("default.toml", "[bot]\npassword_file = \"password.txt\"\n"), ("srv/all.toml", &both)]);
+        assert_eq!(keys(&issues), ["bot.password"]);
+        assert_eq!(issues[0].source, Source::File("srv/all.toml".into()));
+        assert!(issues[0].message.ends_with("also found bot.password_env"), "{}", issues[0].message);
+    }
+}
@@ -0,0 +1,282 @@
+use std::{
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context, Result};
+use hickory_resolver::{
+    config::{NameServerConfigGroup, ResolverConfig, ResolverOpts},
+    TokioAsyncResolver,
+};
+use sysx::io::log::*;
+use tokio::net::TcpStream;
+
+use crate::config::ServerConfig;
+
+pub const DEFAULT_PORT: u16 = 25565;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The server address the bot finally connects to.
+#[derive(Debug, Clone)]
+pub struct ResolvedServer {
+    /// `host:port` sent in the handshake.
+    pub address: String,
+    pub socket: SocketAddr,
+}
+
+/// DNS lookups needed to pick a server address. Implemented by [`DnsResolver`],
+/// tests and local setups can plug in a stub.
+pub trait Resolver {
+    /// Returns `(target, port)` pairs of the SRV records for `name`, best first.
+    fn lookup_srv(&self, name: &str) -> impl Future<Output = Result<Vec<(String, u16)>>> + Send;
+    fn lookup_ip(&self, host: &str) -> impl Future<Output = Result<Vec<IpAddr>>> + Send;
+}
+
+/// Resolver backed by the system DNS config, or by an explicit name server (`server.dns`).
+pub struct DnsResolver(TokioAsyncResolver);
+
+impl DnsResolver {
+    pub fn new(name_server: Option<SocketAddr>) -> Result<Self> {
+        let resolver = match name_server {
+            Some(addr) => {
+                let group = NameServerConfigGroup::from_ips_clear(&[addr.ip()], addr.port(), true);
+                TokioAsyncResolver::tokio(ResolverConfig::from_parts(None, vec![], group), ResolverOpts::default())
+            }
+            None => TokioAsyncResolver::tokio_from_system_conf().context("Failed to read system DNS config")?,
+        };
+        Ok(Self(resolver))
+    }
+}
+
+impl Resolver for DnsResolver {
+    async fn lookup_srv(&self, name: &str) -> Result<Vec<(String, u16)>> {
+        let lookup = self.0.srv_lookup(name).await?;
+        let mut records: Vec<_> = lookup.iter().collect();
+        // Lower priority first, then heavier weight
+        records.sort_by_key(|srv| (srv.priority(), std::cmp::Reverse(srv.weight())));
+        Ok(records
+            .into_iter()
+            .map(|srv| (srv.target().to_utf8().trim_end_matches('.').to_string(), srv.port()))
+            .collect())
+    }
+
+    async fn lookup_ip(&self, host: &str) -> Result<Vec<IpAddr>> {
+        if let Ok(ip) = host.parse() {
+            return Ok(vec![ip]);
+        }
+        Ok(self.0.lookup_ip(host).await?.iter().collect())
+    }
+}
+
+/// Parses `server.dns`: an IP address with an optional port, 53 by default.
+pub fn parse_name_server(dns: &str) -> Result<SocketAddr, String> {
+    let dns = dns.trim();
+    dns.parse::<SocketAddr>()
+        .or_else(|_| dns.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 53)))
+        .map_err(|err| err.to_string())
+}
+
+/// Splits `host[:port]`.
+pub fn parse_address(address: &str) -> Result<(String, Option<u16>), String> {
+    let address = address.trim();
+    let (host, port) = match address.rsplit_once(':') {
+        Some((host, port)) => {
+            let port: u16 = port.parse().map_err(|_| format!("invalid port in {address:?}"))?;
+            if port == 0 {
+                return Err(format!("port in {address:?} must be between 1 and 65535"));
+            }
+            (host, Some(port))
+        }
+        None => (address, None),
+    };
+    if host.is_empty() {
+        return Err(format!("missing host in {address:?}"));
+    }
+    Ok((host.to_string(), port))
+}
+
+/// Addresses to try in order: `host`/`port` first, then `addresses`.
+pub fn candidates(server: &ServerConfig) -> Result<Vec<(String, Option<u16>)>> {
+    let mut candidates = Vec::new();
+    if let Some(host) = &server.host {
+        candidates.push((host.clone(), server.port));
+    }
+    for address in server.addresses.iter().flatten() {
+        candidates.push(parse_address(address).map_err(|err| anyhow!("server.addresses: {}", err))?);
+    }
+    if candidates.is_empty() {
+        return Err(anyhow!("No server address configured"));
+    }
+    Ok(candidates)
+}
+
+/// Every address of the configured candidates, in the order they should be tried: per
+/// candidate its `_minecraft._tcp` SRV targets (for hosts without a port) or the host
+/// itself, each with all of its IPs. Targets that do not resolve are skipped.
+pub async fn resolve_addresses<R: Resolver>(server: &ServerConfig, resolver: &R, portal: &str) -> Result<Vec<ResolvedServer>> {
+    let mut addresses = Vec::new();
+    for (host, port) in candidates(server)? {
+        let targets = match port {
+            Some(port) => vec![(host.clone(), port)],
+            None => match resolver.lookup_srv(&format!("_minecraft._tcp.{host}")).await {
+                Ok(records) if !records.is_empty() => records,
+                _ => vec![(host.clone(), DEFAULT_PORT)],
+            },
+        };
+
+        for (target, port) in targets {
+            match resolver.lookup_ip(&target).await {
+                Ok(ips) if !ips.is_empty() => addresses.extend(ips.into_iter().map(|ip| ResolvedServer {
+                    address: format!("{target}:{port}"),
+                    socket: SocketAddr::new(ip, port),
+                })),
+                Ok(_) | Err(_) => log!(INFO, "[{}] Could not resolve {}, trying the next address", portal, target),
+            }
+        }
+    }
+    if addresses.is_empty() {
+        return Err(anyhow!("None of the configured server addresses resolves"));
+    }
+    Ok(addresses)
+}
+
+/// How long each server address gets to accept a connection or a join.
+pub fn server_timeout(server: &ServerConfig) -> Result<Duration> {
+    match &server.timeout {
+        Some(timeout) => timeout.to_duration().map_err(|err| anyhow!("server.timeout: {}", err)),
+        None => Ok(DEFAULT_TIMEOUT),
+    }
+}
+
+/// Picks the server addresses to join, best first. Directly, every address gets
+/// `server.timeout` to accept a TCP connection and only the first reachable one is
+/// returned. Behind a proxy only the proxy can reach them, so every address is returned
+/// and the join tries them in turn.
+pub async fn resolve_server<R: Resolver>(
+    server: &ServerConfig,
+    resolver: &R,
+    behind_proxy: bool,
+    portal: &str,
+) -> Result<Vec<ResolvedServer>> {
+    let timeout = server_timeout(server)?;
+
+    let addresses = resolve_addresses(server, resolver, portal).await?;
+    if behind_proxy {
+        return Ok(addresses);
+    }
+    for resolved in addresses {
+        match tokio::time::timeout(timeout, TcpStream::connect(resolved.socket)).await {
+            Ok(Ok(_)) => {
+                log!(INFO, "[{}] Using server address {} ({})", portal, resolved.address, resolved.socket);
+                return Ok(vec![resolved]);
+            }
+            Ok(Err(err)) => log!(INFO, "[{}] {} ({}) is unreachable: {}", portal, resolved.address, resolved.socket, err),
+            Err(_) => log!(INFO, "[{}] {} ({}) timed out after {:?}", portal, resolved.address, resolved.socket, timeout),
+        }
+    }
+
+    Err(anyhow!("None of the configured server addresses is reachable"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// Answers from fixed tables, without any DNS traffic.
+    #[derive(Default)]
+    struct StubResolver {
+        srv: HashMap<String, Vec<(String, u16)>>,
+        ips: HashMap<String, Vec<IpAddr>>,
+    }
+
+    impl StubResolver {
+        fn srv(mut self, host: &str, records: &[(&str, u16)]) -> Self {
+            let records = records.iter().map(|(target, port)| (target.to_string(), *port)).collect();
+            self.srv.insert(format!("_minecraft._tcp.{host}"), records);
+            self
+        }
+
+        fn host(mut self, host: &str, ips: &[&str]) -> Self {
+            self.ips.insert(host.to_string(), ips.iter().map(|ip| ip.parse().unwrap()).collect());
+            self
+        }
+    }
+
+    impl Resolver for StubResolver {
+        async fn lookup_srv(&self, name: &str) -> Result<Vec<(String, u16)>> {
+            self.srv.get(name).cloned().ok_or_else(|| anyhow!("no SRV record for {name}"))
+        }
+
+        async fn lookup_ip(&self, host: &str) -> Result<Vec<IpAddr>> {
+            self.ips.get(host).cloned().ok_or_else(|| anyhow!("no address for {host}"))
+        }
+    }
+
+    fn server(host: &str, port: Option<u16>, addresses: &[&str]) -> ServerConfig {
+        ServerConfig {
+            host: Some(host.to_string()),
+            port,
+            addresses: Some(addresses.iter().map(|address| address.to_string()).collect()),
+            timeout: Some(crate::config::ConfigDuration::Seconds(1)),
+            ..Default::default()
+        }
+    }
+
+    fn names(addresses: &[ResolvedServer]) -> Vec<String> {
+        addresses.iter().map(|resolved| format!("{} {}", resolved.address, resolved.socket)).collect()
+    }
+
+    #[tokio::test]
+    async fn tries_srv_then_host_then_fallbacks() {
+        let resolver = StubResolver::default()
+            .srv("mc.example.com", &[("node1.example.com", 25570), ("node2.example.com", 25571)])
+            .host("node1.example.com", &["10.0.0.1"])
+            .host("node2.example.com", &["10.0.0.2", "10.0.0.3"])
+            .host("plain.example.com", &["10.0.1.1"])
+            .host("backup.example.com", &["10.0.2.1"]);
+        let server = server("mc.example.com", None, &["plain.example.com", "unknown.example.com", "backup.example.com:25599"]);
+
+        let addresses = resolve_addresses(&server, &resolver, "test").await.unwrap();
+        assert_eq!(names(&addresses), [
+            "node1.example.com:25570 10.0.0.1:25570",
+            "node2.example.com:25571 10.0.0.2:25571",
+            "node2.example.com:25571 10.0.0.3:25571",
+            "plain.example.com:25565 10.0.1.1:25565",
+            "backup.example.com:25599 10.0.2.1:25599",
+        ]);
+    }
+
+    #[tokio::test]
+    async fn explicit_port_skips_srv() {
+        let resolver = StubResolver::default()
+            .srv("mc.example.com", &[("node1.example.com", 25570)])
+            .host("mc.example.com", &["10.0.0.9"]);
+        let addresses = resolve_addresses(&server("mc.example.com", Some(25565), &[]), &resolver, "test").await.unwrap();
+        assert_eq!(names(&addresses), ["mc.example.com:25565 10.0.0.9:25565"]);
+
+        let unresolvable = server("down.example.com", Some(25565), &[]);
+        assert!(resolve_addresses(&unresolvable, &resolver, "test").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn direct_join_falls_back_to_the_first_reachable_address() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let up = listener.local_addr().unwrap().port();
+        // Bound and dropped, so nothing listens there any more
+        let down = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port();
+        let resolver = StubResolver::default().host("down.test", &["127.0.0.1"]).host("up.test", &["127.0.0.1"]);
+        let server = server("down.test", Some(down), &[&format!("up.test:{up}")]);
+
+        let direct = resolve_server(&server, &resolver, false, "test").await.unwrap();
+        assert_eq!(names(&direct), [format!("up.test:{up} 127.0.0.1:{up}")]);
+
+        // Behind a proxy nothing is probed, the join tries every address in order
+        let proxied = resolve_server(&server, &resolver, true, "test").await.unwrap();
+        assert_eq!(names(&proxied), [format!("down.test:{down} 127.0.0.1:{down}"), format!("up.test:{up} 127.0.0.1:{up}")]);
+    }
+}
@@ -1,2 +0,0 @@
-pub const JOIN_PORTAL_MSG1: &str = "› Вы успешно авторизовались";
-pub const JOIN_PORTAL_MSG2: &str = "› Вы уже авторизовались";
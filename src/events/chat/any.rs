@@ -0,0 +1,15 @@
+use azalea::prelude::*;
+use crate::chat::{ChatChannel, ChatMessage};
+use crate::events::chat::parser::react;
+use crate::types::State;
+
+/// Every line, whatever its channel. The chat itself goes to stdout as it always did,
+/// prefixed with the portal id like the log; unparsed lines are logged by the `unknown`
+/// handler instead.
+pub fn handle(bot: &Client, state: &State, message: &ChatMessage) {
+    if message.channel != ChatChannel::Unknown {
+        let portal = state.config.read().bot.id.clone();
+        println!("[{}] {}", portal, message.formatted.to_ansi());
+    }
+    react(bot, state, message, None);
+}
@@ -0,0 +1,8 @@
+use azalea::prelude::*;
+use crate::chat::{ChatChannel, ChatMessage};
+use crate::events::chat::parser::react;
+use crate::types::State;
+
+pub fn handle(bot: &Client, state: &State, message: &ChatMessage) {
+    react(bot, state, message, Some(ChatChannel::Global));
+}
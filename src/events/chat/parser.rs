@@ -1,22 +1,66 @@
 use azalea::{chat::ChatPacket, prelude::*};
-use crate::consts::*;
+use sysx::io::log::*;
+use crate::{auth, navigation, outbox};
+use crate::outbox::{Outgoing, Priority};
+use crate::chat::{ChatChannel, ChatMessage};
+use crate::config::ReactionAction;
+use crate::events::chat::{any, clan, global, local, personal, system, unknown};
 use crate::types::State;
 
+/// Parses a chat line, lets the outbox, login and navigation flows see it when nobody said
+/// it, then hands it to the `any` handler and the handler of its channel.
 pub fn chat_parser(bot: Client, state: State, msg: ChatPacket) {
-    let portal = state.config.bot.portal.clone();
-    let text = msg.content();
+    let (nickname, patterns) = {
+        let config = state.config.read();
+        (config.bot.nickname.clone(), config.patterns.clone())
+    };
+    let message = ChatMessage::parse(&msg, &nickname, &patterns);
 
-    if msg.sender() == Some(bot.username()) {
-        // return Ok(());
+    state.update(|session| session.counters.chat += 1);
+    if let Some(text) = message.server_text() {
+        outbox::on_chat(&state, text);
+        auth::on_chat(&bot, &state, text);
+        navigation::on_chat(&bot, &state, text);
     }
-    if text.contains(JOIN_PORTAL_MSG1) || text.contains(JOIN_PORTAL_MSG2) {
-        bot.send_command_packet(&portal);
+
+    any::handle(&bot, &state, &message);
+    match message.channel {
+        ChatChannel::Global => global::handle(&bot, &state, &message),
+        ChatChannel::Local => local::handle(&bot, &state, &message),
+        ChatChannel::Clan => clan::handle(&bot, &state, &message),
+        ChatChannel::Personal => personal::handle(&bot, &state, &message),
+        ChatChannel::System => system::handle(&bot, &state, &message),
+        ChatChannel::Unknown => unknown::handle(&bot, &state, &message),
     }
-    if text.contains("/spam") && text.contains("zxclyric") {
-        for _ in 0..10 {
-            bot.chat("lol");
-        }
+}
+
+/// Runs the `chat.reactions` of `channel`, `None` being those of the `any` handler.
+pub fn react(bot: &Client, state: &State, message: &ChatMessage, channel: Option<ChatChannel>) {
+    // The bot's own lines never trigger reactions, or a reply could answer itself
+    let (portal, nickname, reactions) = {
+        let config = state.config.read();
+        (config.bot.id.clone(), config.bot.nickname.clone(), config.reactions.clone())
+    };
+    if message.is_from(&nickname) || state.session.read().flags.paused {
+        return;
     }
 
-    println!("{}", msg.message().to_ansi());
+    let cooling_down = |name: &str, cooldown| state.try_use(name, cooldown).is_some();
+    for fired in reactions.fire(channel, message, cooling_down) {
+        match fired.action {
+            ReactionAction::Log => log!(INFO, "[{}] Reaction {}: {}", portal, fired.rule, message),
+            ReactionAction::Alert => log!(INFO, "[{}] ALERT (reaction {}): {}", portal, fired.rule, message),
+            ReactionAction::Say if fired.is_injected_command() => {
+                log!(INFO, "[{}] Reaction {}: refused to say {:?}, only configured texts may run commands", portal, fired.rule, fired.text);
+            }
+            ReactionAction::Say => outbox::send(bot, state, Priority::Replies, Outgoing::Chat(fired.text)),
+            ReactionAction::Reply => match &message.sender {
+                Some(sender) => {
+                    let text = format!("/msg {} {}", sender, fired.text);
+                    outbox::send(bot, state, Priority::Replies, Outgoing::Chat(text));
+                }
+                None => log!(INFO, "[{}] Reaction {}: cannot reply to a line without sender", portal, fired.rule),
+            },
+        }
+    }
 }
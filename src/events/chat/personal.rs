@@ -0,0 +1,20 @@
+use azalea::prelude::*;
+use sysx::io::log::*;
+use crate::chat::{ChatChannel, ChatMessage};
+use crate::commands;
+use crate::events::chat::parser::react;
+use crate::types::State;
+
+/// Private messages, logged since nobody else sees them. Commands from owners stop here.
+pub fn handle(bot: &Client, state: &State, message: &ChatMessage) {
+    let portal = state.config.read().bot.id.clone();
+    let sender = message.sender.as_deref().unwrap_or("?");
+    match &message.recipient {
+        Some(recipient) => log!(INFO, "[{}] PM {} -> {}: {}", portal, sender, recipient, message.body),
+        None => log!(INFO, "[{}] PM from {}: {}", portal, sender, message.body),
+    }
+    if commands::on_message(bot, state, message) {
+        return;
+    }
+    react(bot, state, message, Some(ChatChannel::Personal));
+}
@@ -0,0 +1,21 @@
+use azalea::prelude::*;
+use sysx::io::log::*;
+use crate::chat::{ChatChannel, ChatMessage};
+use crate::config::Pattern;
+use crate::events::chat::parser::react;
+use crate::types::State;
+
+/// Server messages known to the pattern catalog.
+pub fn handle(bot: &Client, state: &State, message: &ChatMessage) {
+    let (portal, patterns) = {
+        let config = state.config.read();
+        (config.bot.id.clone(), config.patterns.clone())
+    };
+    if patterns.is_match(Pattern::Muted, &message.text) {
+        log!(INFO, "[{}] Muted: {}", portal, message.text);
+    }
+    if patterns.is_match(Pattern::Banned, &message.text) {
+        log!(INFO, "[{}] Banned: {}", portal, message.text);
+    }
+    react(bot, state, message, Some(ChatChannel::System));
+}
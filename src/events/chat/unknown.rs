@@ -0,0 +1,12 @@
+use azalea::prelude::*;
+use sysx::io::log::*;
+use crate::chat::{ChatChannel, ChatMessage};
+use crate::events::chat::parser::react;
+use crate::types::State;
+
+/// Lines no `chat.*` format matched. The raw text is logged to help write the formats.
+pub fn handle(bot: &Client, state: &State, message: &ChatMessage) {
+    let portal = state.config.read().bot.id.clone();
+    log!(INFO, "[{}] Unparsed chat line: {:?}", portal, message.text);
+    react(bot, state, message, Some(ChatChannel::Unknown));
+}
@@ -1,10 +1,13 @@
 use azalea::FormattedText;
 use sysx::io::log::*;
-use crate::types::*;
+use crate::{supervisor::BotSignal, types::*};
 
 pub fn disconnect_handler(state: State, reason: Option<FormattedText>) {
-    let portal = state.config.bot.portal.clone();
-    let text = reason.unwrap_or_default().to_ansi();
+    let portal = state.config.read().bot.id.clone();
+    state.auth_slot.release();
+    let reason = reason.unwrap_or_default();
+    let text = reason.to_ansi();
 
     log!(INFO, "[{}] Disconnected: {}", portal, text);
+    state.signals.send(BotSignal::Disconnected(reason.to_string()));
 }
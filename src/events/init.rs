@@ -1,5 +1,7 @@
+use azalea::prelude::*;
 use crate::types::State;
 
-pub fn init_handler(mut state: State) {
-    state.flags.init = true;
+pub fn init_handler(bot: Client, state: State) {
+    state.update(|session| session.flags.init = true);
+    *state.client.lock() = Some(bot);
 }
@@ -1,10 +1,8 @@
 use azalea::prelude::*;
-use crate::types::*;
+use crate::{auth, navigation, types::*};
 
-pub fn login_handler(bot: Client, mut state: State) {
-    state.flags.login = true;
-    let password = state.config.bot.password.clone();
-
-    bot.chat(format!("/reg {password}").as_str());
-    bot.chat(format!("/login {password}").as_str());
+pub fn login_handler(_bot: Client, state: State) {
+    state.update(|session| session.flags.login = true);
+    navigation::on_login(&state);
+    auth::on_login(&state);
 }
@@ -1,16 +1,10 @@
 use std::sync::Arc;
 
 use azalea::{prelude::*, protocol::packets::game::ClientboundGamePacket};
-use crate::types::State;
+use crate::{navigation, types::State};
 
-pub fn packet_parser(bot: Client, state: State, packet: Arc<ClientboundGamePacket>) {
-    match packet.as_ref() {
-        ClientboundGamePacket::PlayerPosition(position) => {
-            let warp = state.config.bot.warp.clone();
-            let cmd = format!("/warp {warp}");
-            bot.chat(cmd.as_str());
-        }
-
-        _ => {}
+pub fn packet_parser(_bot: Client, state: State, packet: Arc<ClientboundGamePacket>) {
+    if let ClientboundGamePacket::PlayerPosition(_) = packet.as_ref() {
+        navigation::on_position(&state);
     }
 }
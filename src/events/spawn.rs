@@ -1,5 +1,7 @@
-use crate::types::*;
+use azalea::prelude::*;
+use crate::{navigation, types::*};
 
-pub fn spawn_handler(mut state: State) {
-    state.counters.spawn += 1;
+pub fn spawn_handler(bot: Client, state: State) {
+    navigation::on_spawn(&bot, &state);
+    state.update(|session| session.counters.spawn += 1);
 }
@@ -1,16 +1,13 @@
 use azalea::prelude::*;
-use crate::types::State;
+use crate::{auth, navigation, outbox, types::State};
 
-pub fn tick_handler(bot: Client, mut state: State) {
-    /*
-    let pos = bot.position();
-
-    if pos.distance_to(&state.prev_pos) > 1.0 {
-        let warp = state.config.bot.warp.clone();
-        let cmd = format!("/warp {warp:?}");
-        bot.chat(cmd.as_str());
-    }
-
-    state.prev_pos = pos;
-    */
+pub fn tick_handler(bot: Client, state: State) {
+    let position = bot.position();
+    state.update(|session| {
+        session.counters.ticks += 1;
+        session.prev_pos = position;
+    });
+    auth::on_tick(&bot, &state);
+    navigation::on_tick(&bot, &state);
+    outbox::on_tick(&bot, &state);
 }
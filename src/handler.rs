@@ -5,15 +5,17 @@ use crate::events::packet::parser::packet_parser;
 use crate::{events::spawn::spawn_handler, types::*};
 use azalea::prelude::*;
 use crate::events::disconnect::disconnect_handler;
+use crate::events::tick::tick_handler;
 
 pub async fn handle(bot: Client, event: Event, state: State) -> anyhow::Result<()> {
     match event {
-        Event::Init => init_handler(state),
+        Event::Init => init_handler(bot, state),
         Event::Login => login_handler(bot, state),
-        Event::Spawn => spawn_handler(state),
+        Event::Spawn => spawn_handler(bot, state),
         Event::Chat(msg) => chat_parser(bot, state, msg),
         Event::Disconnect(reason) => disconnect_handler(state, reason),
         Event::Packet(packet) => packet_parser(bot, state, packet),
+        Event::Tick => tick_handler(bot, state),
         _ => {}
     }
 
@@ -1,10 +1,24 @@
+pub mod auth;
+pub mod bot;
+pub mod chat;
+pub mod commands;
 pub mod config;
-pub mod consts;
+pub mod connect;
 pub mod deadlock;
 pub mod handler;
+pub mod navigation;
+pub mod outbox;
 pub mod re;
+pub mod reload;
+pub mod schedule;
+pub mod store;
+pub mod supervisor;
+pub mod swarm;
 pub mod types;
 
+#[cfg(test)]
+mod testing;
+
 pub mod events {
     pub mod disconnect;
     pub mod init;
@@ -24,6 +38,7 @@ pub mod events {
         pub mod local;
         pub mod parser;
         pub mod personal;
+        pub mod system;
         pub mod unknown;
     }
 }
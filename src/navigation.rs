@@ -0,0 +1,287 @@
+use std::{fmt, time::Duration};
+
+use azalea::{prelude::*, Vec3};
+use sysx::io::log::*;
+use tokio::time::Instant;
+
+use crate::{
+    config::Pattern,
+    outbox::{self, Outgoing, Priority},
+    supervisor::BotSignal,
+    types::State,
+};
+
+/// Where the bot is on its way from the hub to its warp.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Stage {
+    /// Not connected yet.
+    #[default]
+    Lobby,
+    /// Logging in, see `AuthFlow`.
+    Auth,
+    /// The portal command was sent, waiting to arrive on the portal server.
+    Portal,
+    /// The warp command was sent, waiting to be teleported.
+    Warp,
+    Arrived,
+    Failed,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Stage::Lobby => "lobby",
+            Stage::Auth => "auth",
+            Stage::Portal => "portal",
+            Stage::Warp => "warp",
+            Stage::Arrived => "arrived",
+            Stage::Failed => "failed",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Navigation from the hub to the warp. Each stage sends its command once and waits for the
+/// server to confirm it; unconfirmed commands are sent again after `navigation.timeout` up
+/// to `navigation.max_attempts` times. Only what happens once the outbox actually sent the
+/// command confirms it: a chat line, a respawn into another world, or being moved farther
+/// than `navigation.confirm_distance`; a warp back also by being moved to within that
+/// distance of where the warp put the bot before.
+#[derive(Debug, Default)]
+pub struct Navigation {
+    stage: Stage,
+    /// When the command of the current stage was last sent.
+    since: Option<Instant>,
+    /// Commands sent in the current stage.
+    attempts: u32,
+    /// Navigation messages the outbox had sent when the current command was queued, the
+    /// command is out once the count goes past it.
+    sent_before: u64,
+    /// Where the bot was when the command of the current stage was sent.
+    origin: Option<Vec3>,
+    /// The server moved the bot since the last tick.
+    repositioned: bool,
+    /// Where the warp put the bot.
+    warp_position: Option<Vec3>,
+    /// When `/warp` was last sent, for `navigation.warp_cooldown`.
+    last_warp: Option<Instant>,
+}
+
+impl Navigation {
+    pub fn stage(&self) -> Stage {
+        self.stage
+    }
+
+    pub fn warp_position(&self) -> Option<Vec3> {
+        self.warp_position
+    }
+}
+
+/// Starts over on a fresh connection, the auth flow runs first.
+pub fn on_login(state: &State) {
+    let mut nav = state.nav.lock();
+    *nav = Navigation::default();
+    transition(state, &mut nav, Stage::Auth, "joined");
+}
+
+/// Logged in, heads for the portal.
+pub fn on_authenticated(bot: &Client, state: &State) {
+    let mut nav = state.nav.lock();
+    transition(state, &mut nav, Stage::Portal, "authenticated");
+    send(bot, state, &mut nav);
+}
+
+pub fn on_chat(bot: &Client, state: &State, text: &str) {
+    let patterns = state.config.read().patterns.clone();
+    let mut nav = state.nav.lock();
+    match nav.stage {
+        Stage::Portal | Stage::Warp | Stage::Arrived if patterns.is_match(Pattern::BackInLobby, text) => {
+            // The hub keeps the session, so only the portal has to be entered again
+            transition(state, &mut nav, Stage::Portal, "back in the lobby");
+            send(bot, state, &mut nav);
+        }
+        Stage::Portal if patterns.is_match(Pattern::PortalJoined, text) && command_sent(state, &nav) => {
+            portal_joined(bot, state, &mut nav, "portal joined");
+        }
+        Stage::Warp if patterns.is_match(Pattern::Teleported, text) && command_sent(state, &nav) => {
+            arrived(state, &mut nav, "teleported");
+        }
+        _ => {}
+    }
+}
+
+/// The server switch of the portal respawns the bot in the new world. Spawn events only
+/// come with a new world, on joining and on every respawn.
+pub fn on_spawn(bot: &Client, state: &State) {
+    let mut nav = state.nav.lock();
+    if nav.stage == Stage::Portal && command_sent(state, &nav) {
+        portal_joined(bot, state, &mut nav, "respawned in another world");
+    }
+}
+
+/// The server moved the bot, which both the server switch and the warp do. The new
+/// position is only applied after the packet, so the move is measured on the next tick.
+pub fn on_position(state: &State) {
+    let mut nav = state.nav.lock();
+    if matches!(nav.stage, Stage::Portal | Stage::Warp) {
+        nav.repositioned = true;
+    }
+}
+
+/// Enters the portal again once logged in. Returns `false` before that.
+pub fn reenter_portal(bot: &Client, state: &State, why: &str) -> bool {
+    let mut nav = state.nav.lock();
+    if matches!(nav.stage, Stage::Lobby | Stage::Auth | Stage::Failed) {
+        return false;
+    }
+    transition(state, &mut nav, Stage::Portal, why);
+    send(bot, state, &mut nav);
+    true
+}
+
+/// Warps again right away, cooldown or not. Returns `false` when not on the portal yet.
+pub fn rewarp(bot: &Client, state: &State, why: &str) -> bool {
+    let mut nav = state.nav.lock();
+    if !matches!(nav.stage, Stage::Warp | Stage::Arrived) {
+        return false;
+    }
+    transition(state, &mut nav, Stage::Warp, why);
+    send(bot, state, &mut nav);
+    true
+}
+
+/// Sends the command of a stage the server did not confirm in time again or gives up,
+/// and warps back once moved too far from the warp.
+pub fn on_tick(bot: &Client, state: &State) {
+    let mut nav = state.nav.lock();
+    let navigation = state.config.read().navigation.clone();
+    if nav.stage == Stage::Arrived {
+        if state.session.read().flags.paused {
+            return;
+        }
+        enforce_warp(bot, state, &mut nav, navigation.warp_distance, navigation.warp_cooldown);
+        return;
+    }
+    if nav.repositioned && nav.origin.is_some() {
+        nav.repositioned = false;
+        if confirm_move(bot, state, &mut nav, navigation.confirm_distance) {
+            return;
+        }
+    }
+    let Some(since) = nav.since else { return };
+    if !matches!(nav.stage, Stage::Portal | Stage::Warp) || since.elapsed() < navigation.timeout {
+        return;
+    }
+
+    if nav.attempts >= navigation.max_attempts {
+        let why = format!("not confirmed after {} attempt(s)", nav.attempts);
+        let stage = nav.stage;
+        transition(state, &mut nav, Stage::Failed, &why);
+        nav.since = None;
+        state.signals.send(BotSignal::Disconnected(format!("failed to reach the {stage}: {why}")));
+        bot.disconnect();
+        return;
+    }
+    log!(INFO, "[{}] Navigation: timed out in {}, retrying", portal(state), nav.stage);
+    send(bot, state, &mut nav);
+}
+
+fn send(bot: &Client, state: &State, nav: &mut Navigation) {
+    let bot_config = state.config.read().bot.clone();
+    // Read before queueing, the outbox may send the command right away
+    nav.sent_before = state.outbox.lock().sent(Priority::Navigation);
+    match nav.stage {
+        Stage::Portal => outbox::replace(bot, state, Priority::Navigation, Outgoing::Command(bot_config.portal)),
+        Stage::Warp => {
+            outbox::replace(bot, state, Priority::Navigation, Outgoing::Chat(format!("/warp {}", bot_config.warp)));
+            nav.last_warp = Some(Instant::now());
+        }
+        _ => return,
+    }
+    nav.attempts += 1;
+    nav.since = Some(Instant::now());
+    nav.origin = Some(bot.position());
+}
+
+/// Whether the command of the current stage left the outbox. Navigation is the only
+/// sender of its priority and replaces what it queued before, so the next one sent is it.
+fn command_sent(state: &State, nav: &Navigation) -> bool {
+    state.outbox.lock().sent(Priority::Navigation) > nav.sent_before
+}
+
+/// Confirms the current stage when the server moved the bot farther than `distance` since
+/// its command was sent, or back to within `distance` of the warp when warping back.
+fn confirm_move(bot: &Client, state: &State, nav: &mut Navigation, distance: f64) -> bool {
+    let Some(origin) = nav.origin else { return false };
+    let position = bot.position();
+    if !command_sent(state, nav) {
+        // Not caused by the command, measure from here instead
+        nav.origin = Some(position);
+        return false;
+    }
+    let why = match nav.warp_position {
+        Some(warp_position) if nav.stage == Stage::Warp => {
+            let away = position.distance_to(&warp_position);
+            if away > distance {
+                return false;
+            }
+            format!("back within {away:.1} blocks of the warp")
+        }
+        _ => {
+            let moved = position.distance_to(&origin);
+            if moved <= distance {
+                return false;
+            }
+            format!("moved {moved:.1} blocks")
+        }
+    };
+    match nav.stage {
+        Stage::Portal => portal_joined(bot, state, nav, &why),
+        Stage::Warp => arrived(state, nav, &why),
+        _ => return false,
+    }
+    true
+}
+
+/// Warps again when something moved the bot farther than `distance` from where the warp
+/// put it, at most once per `cooldown`.
+fn enforce_warp(bot: &Client, state: &State, nav: &mut Navigation, distance: f64, cooldown: Duration) {
+    // Taken on the first tick after arriving, once the teleport has been applied
+    let Some(warp_position) = nav.warp_position else {
+        nav.warp_position = Some(bot.position());
+        return;
+    };
+    let moved = bot.position().distance_to(&warp_position);
+    if moved <= distance || nav.last_warp.is_some_and(|last| last.elapsed() < cooldown) {
+        return;
+    }
+    transition(state, nav, Stage::Warp, &format!("{moved:.1} blocks away"));
+    send(bot, state, nav);
+}
+
+fn transition(state: &State, nav: &mut Navigation, to: Stage, why: &str) {
+    log!(INFO, "[{}] Navigation: {} -> {} ({})", portal(state), nav.stage, to, why);
+    nav.stage = to;
+    nav.attempts = 0;
+    nav.since = None;
+    nav.origin = None;
+    nav.repositioned = false;
+    if to == Stage::Portal {
+        // The warp may be somewhere else after entering the portal again
+        nav.warp_position = None;
+    }
+}
+
+fn portal_joined(bot: &Client, state: &State, nav: &mut Navigation, why: &str) {
+    transition(state, nav, Stage::Warp, why);
+    send(bot, state, nav);
+}
+
+fn arrived(state: &State, nav: &mut Navigation, why: &str) {
+    transition(state, nav, Stage::Arrived, why);
+    nav.warp_position = None;
+}
+
+fn portal(state: &State) -> String {
+    state.config.read().bot.id.clone()
+}
@@ -0,0 +1,300 @@
+use std::{collections::VecDeque, fmt, time::Duration};
+
+use azalea::prelude::*;
+use sysx::io::log::*;
+use tokio::time::Instant;
+
+use crate::{
+    config::{OutboxResolved, Pattern},
+    types::State,
+};
+
+/// How soon after a message a flood warning has to come to be about it. A later one is
+/// about something older, so nothing is sent again.
+const REFUSAL_WINDOW: Duration = Duration::from_secs(2);
+
+/// How urgent an outgoing message is. Higher ones always go first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Ads,
+    Replies,
+    Navigation,
+    Auth,
+}
+
+impl Priority {
+    const ALL: [Priority; 4] = [Priority::Ads, Priority::Replies, Priority::Navigation, Priority::Auth];
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Priority::Ads => "ads",
+            Priority::Replies => "replies",
+            Priority::Navigation => "navigation",
+            Priority::Auth => "auth",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outgoing {
+    /// A chat line, commands written with their `/`.
+    Chat(String),
+    /// A command sent as a command packet, without the `/`.
+    Command(String),
+}
+
+/// Messages waiting to be sent by one bot, paced by a minimum interval and a token bucket.
+/// When the server says the bot is too fast, every delay is stretched and the last message
+/// is sent again if it was just sent; the stretch wears off after a while without complaints.
+#[derive(Debug)]
+pub struct Outbox {
+    /// One queue per priority, indexed by `Priority as usize`.
+    queues: [VecDeque<Outgoing>; 4],
+    last_sent: Option<(Instant, Priority, Outgoing)>,
+    /// Messages sent so far per priority, indexed like `queues`.
+    sent: [u64; 4],
+    tokens: f64,
+    refilled_at: Instant,
+    slowdown: f64,
+    slowed_at: Option<Instant>,
+}
+
+impl Default for Outbox {
+    fn default() -> Self {
+        Self {
+            queues: Default::default(),
+            last_sent: None,
+            sent: [0; 4],
+            // Starts full, the first few messages after joining go out right away
+            tokens: f64::MAX,
+            refilled_at: Instant::now(),
+            slowdown: 1.0,
+            slowed_at: None,
+        }
+    }
+}
+
+impl Outbox {
+    pub fn len(&self) -> usize {
+        self.queues.iter().map(VecDeque::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// How many messages of `priority` went out so far, those refused by the server excepted.
+    /// Lets a caller tell when something it queued has actually been sent.
+    pub fn sent(&self, priority: Priority) -> u64 {
+        self.sent[priority as usize]
+    }
+
+    /// How many times slower than configured the bot currently talks.
+    pub fn slowdown(&self) -> f64 {
+        self.slowdown
+    }
+
+    fn push(&mut self, priority: Priority, message: Outgoing, limits: &OutboxResolved) -> Option<Outgoing> {
+        self.queues[priority as usize].push_back(message);
+        if self.len() <= limits.max_queued as usize {
+            return None;
+        }
+        // Full: the newest message of the least important non-empty queue goes
+        let lowest = Priority::ALL.into_iter().find(|priority| !self.queues[*priority as usize].is_empty())?;
+        self.queues[lowest as usize].pop_back()
+    }
+
+    /// Takes the next message if the pacing allows one at `now`.
+    fn next(&mut self, limits: &OutboxResolved, now: Instant) -> Option<(Priority, Outgoing)> {
+        self.recover(limits, now);
+        self.refill(limits, now);
+        if self.tokens < 1.0 {
+            return None;
+        }
+        if let Some((sent_at, ..)) = &self.last_sent
+            && now.duration_since(*sent_at) < limits.min_interval.mul_f64(self.slowdown)
+        {
+            return None;
+        }
+
+        let priority = Priority::ALL.into_iter().rev().find(|priority| !self.queues[*priority as usize].is_empty())?;
+        let message = self.queues[priority as usize].pop_front()?;
+        self.tokens -= 1.0;
+        self.sent[priority as usize] += 1;
+        self.last_sent = Some((now, priority, message.clone()));
+        Some((priority, message))
+    }
+
+    fn refill(&mut self, limits: &OutboxResolved, now: Instant) {
+        let refill = limits.refill.mul_f64(self.slowdown).max(Duration::from_millis(1));
+        let earned = now.duration_since(self.refilled_at).as_secs_f64() / refill.as_secs_f64();
+        self.tokens = (self.tokens + earned).min(limits.burst as f64);
+        self.refilled_at = now;
+    }
+
+    fn recover(&mut self, limits: &OutboxResolved, now: Instant) {
+        if let Some(slowed_at) = self.slowed_at
+            && now.duration_since(slowed_at) >= limits.recover_after
+        {
+            self.slowdown = (self.slowdown / 2.0).max(1.0);
+            self.slowed_at = (self.slowdown > 1.0).then_some(now);
+        }
+    }
+
+    /// The server says the bot is too fast: talk slower, and queue the last message again,
+    /// first in line, when it went out within [`REFUSAL_WINDOW`].
+    fn too_fast(&mut self, limits: &OutboxResolved, now: Instant) {
+        self.slowdown = (self.slowdown * 2.0).min(limits.max_slowdown.max(1.0));
+        self.slowed_at = Some(now);
+        self.tokens = 0.0;
+        if let Some((sent_at, priority, message)) = self.last_sent.take()
+            && now.duration_since(sent_at) <= REFUSAL_WINDOW
+        {
+            self.sent[priority as usize] -= 1;
+            self.queues[priority as usize].push_front(message);
+        }
+    }
+}
+
+/// Queues `message` and sends whatever the pacing allows right away.
+pub fn send(bot: &Client, state: &State, priority: Priority, message: Outgoing) {
+    let limits = state.config.read().outbox.clone();
+    let dropped = state.outbox.lock().push(priority, message, &limits);
+    if dropped.is_some() {
+        log!(INFO, "[{}] Outbox full ({} queued), dropped a message", portal(state), limits.max_queued);
+    }
+    flush(bot, state);
+}
+
+/// Like [`send`], but first drops what is still queued with the same priority. For steps
+/// such as logging in where only the latest command matters.
+pub fn replace(bot: &Client, state: &State, priority: Priority, message: Outgoing) {
+    state.outbox.lock().queues[priority as usize].clear();
+    send(bot, state, priority, message);
+}
+
+pub fn on_tick(bot: &Client, state: &State) {
+    flush(bot, state);
+}
+
+/// Slows down when the server complains about the pace. Only gets server lines, a player
+/// saying the words must not make the bot repeat itself.
+pub fn on_chat(state: &State, text: &str) {
+    let (limits, patterns) = {
+        let config = state.config.read();
+        (config.outbox.clone(), config.patterns.clone())
+    };
+    if !patterns.is_match(Pattern::TooFast, text) {
+        return;
+    }
+    let mut outbox = state.outbox.lock();
+    outbox.too_fast(&limits, Instant::now());
+    log!(INFO, "[{}] Outbox: server says too fast, slowing down to {}x", portal(state), outbox.slowdown);
+}
+
+fn flush(bot: &Client, state: &State) {
+    let limits = state.config.read().outbox.clone();
+    let Some((_, message)) = state.outbox.lock().next(&limits, Instant::now()) else { return };
+    match message {
+        Outgoing::Chat(text) => bot.chat(text.as_str()),
+        Outgoing::Command(command) => bot.send_command_packet(&command),
+    }
+}
+
+fn portal(state: &State) -> String {
+    state.config.read().bot.id.clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits() -> OutboxResolved {
+        OutboxResolved {
+            min_interval: Duration::from_secs(1),
+            burst: 10,
+            refill: Duration::from_secs(3),
+            max_slowdown: 4.0,
+            recover_after: Duration::from_secs(60),
+            max_queued: 4,
+        }
+    }
+
+    fn chat(text: &str) -> Outgoing {
+        Outgoing::Chat(text.to_string())
+    }
+
+    fn text(sent: Option<(Priority, Outgoing)>) -> Option<String> {
+        match sent? {
+            (_, Outgoing::Chat(text) | Outgoing::Command(text)) => Some(text),
+        }
+    }
+
+    #[test]
+    fn higher_priorities_go_first_and_the_lowest_is_dropped_when_full() {
+        let limits = limits();
+        let mut outbox = Outbox::default();
+        let start = Instant::now();
+        assert!(outbox.push(Priority::Ads, chat("ad 1"), &limits).is_none());
+        assert!(outbox.push(Priority::Replies, chat("reply"), &limits).is_none());
+        assert!(outbox.push(Priority::Ads, chat("ad 2"), &limits).is_none());
+        assert!(outbox.push(Priority::Auth, chat("/login"), &limits).is_none());
+        assert!(matches!(outbox.push(Priority::Navigation, chat("/warp"), &limits), Some(Outgoing::Chat(text)) if text == "ad 2"));
+
+        let sent: Vec<_> = (0..4).filter_map(|n| text(outbox.next(&limits, start + Duration::from_secs(n)))).collect();
+        assert_eq!(sent, ["/login", "/warp", "reply", "ad 1"]);
+        assert!(outbox.is_empty());
+        assert_eq!(outbox.sent(Priority::Ads), 1);
+    }
+
+    #[test]
+    fn pacing_keeps_the_interval_and_the_burst() {
+        let limits = OutboxResolved { burst: 2, refill: Duration::from_secs(10), ..limits() };
+        let mut outbox = Outbox::default();
+        for n in 0..4 {
+            outbox.push(Priority::Replies, chat(&n.to_string()), &limits);
+        }
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+
+        assert_eq!(text(outbox.next(&limits, at(0))).as_deref(), Some("0"));
+        assert_eq!(outbox.next(&limits, at(500)), None);
+        assert_eq!(text(outbox.next(&limits, at(1000))).as_deref(), Some("1"));
+        // The burst of 2 is spent, a new token takes 10s to earn
+        assert_eq!(outbox.next(&limits, at(2000)), None);
+        assert_eq!(outbox.next(&limits, at(9000)), None);
+        assert_eq!(text(outbox.next(&limits, at(10500))).as_deref(), Some("2"));
+    }
+
+    #[test]
+    fn flood_warnings_slow_down_until_they_stop() {
+        let limits = limits();
+        let mut outbox = Outbox::default();
+        outbox.push(Priority::Replies, chat("hello"), &limits);
+        outbox.push(Priority::Replies, chat("world"), &limits);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert_eq!(text(outbox.next(&limits, at(0))).as_deref(), Some("hello"));
+        outbox.too_fast(&limits, at(1));
+        assert_eq!(outbox.slowdown(), 2.0);
+        assert_eq!(outbox.sent(Priority::Replies), 0);
+        // Sent again first, once the slower pace allows it
+        assert_eq!(outbox.next(&limits, at(5)), None);
+        assert_eq!(text(outbox.next(&limits, at(7))).as_deref(), Some("hello"));
+
+        // Too late to be about the last message: slower, but nothing is sent again
+        outbox.too_fast(&limits, at(20));
+        outbox.too_fast(&limits, at(30));
+        assert_eq!(outbox.slowdown(), 4.0);
+        assert_eq!(outbox.len(), 1);
+
+        assert_eq!(text(outbox.next(&limits, at(90))).as_deref(), Some("world"));
+        assert_eq!(outbox.slowdown(), 2.0);
+        outbox.next(&limits, at(150));
+        assert_eq!(outbox.slowdown(), 1.0);
+    }
+}
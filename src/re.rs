@@ -0,0 +1,9 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    /// Minecraft username: 3-16 latin letters, digits or underscores.
+    pub static ref NICKNAME: Regex = Regex::new(r"^[A-Za-z0-9_]{3,16}$").unwrap();
+    /// Player UUID in its usual hyphenated form.
+    pub static ref UUID: Regex = Regex::new(r"^(?i)[0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12}$").unwrap();
+}
@@ -0,0 +1,137 @@
+use std::{
+    collections::HashSet,
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context, Result};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use parking_lot::RwLock;
+use sysx::io::log::*;
+use tokio::sync::{mpsc, Notify};
+
+use crate::bot;
+use crate::config::{load_cfg, LoadedConfig, RuntimeConfig, PATTERNS_FILE};
+
+/// Editors usually write a file in several steps, wait for them to settle before reloading.
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Watches every file of the portal's config chain and the server's `patterns.toml`. On
+/// change the config is reloaded and validated; live settings are swapped into `config`
+/// right away, while changes that need a new connection are reported and signalled
+/// through `reconnect`. Any other change of the config is signalled through `changed`,
+/// which wakes a stopped or parked portal, e.g. after fixing its password.
+///
+/// The returned watcher must be kept alive for as long as the bot runs.
+pub fn watch_config(
+    loaded: &LoadedConfig,
+    config: Arc<RwLock<RuntimeConfig>>,
+    reconnect: Arc<Notify>,
+    changed: Arc<Notify>,
+) -> Result<RecommendedWatcher> {
+    // The server's patterns.toml may not exist yet, creating it counts as a change too
+    let patterns = loaded.portal_path.parent().unwrap_or(Path::new("")).join(PATTERNS_FILE);
+    let files = loaded.provenance.files().iter().chain(std::iter::once(&patterns));
+    let mut targets = HashSet::new();
+    let mut dirs = HashSet::new();
+    for file in files {
+        let dir = match file.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let dir = dir.canonicalize()
+            .context(format!("Failed to resolve config directory: {}", dir.display()))?;
+        if let Some(name) = file.file_name() {
+            targets.insert(dir.join(name));
+        }
+        dirs.insert(dir);
+    }
+
+    let (tx, rx) = mpsc::unbounded_channel();
+    // Watch the directories rather than the files, editors often replace files on save
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let Ok(event) = event else { return };
+        if matches!(event.kind, EventKind::Access(_)) {
+            return;
+        }
+        if event.paths.iter().any(|path| targets.contains(path)) {
+            let _ = tx.send(());
+        }
+    })?;
+    for dir in &dirs {
+        watcher.watch(dir, RecursiveMode::NonRecursive)
+            .context(format!("Failed to watch config directory: {}", dir.display()))?;
+    }
+
+    tokio::spawn(reload_loop(loaded.clone(), config, reconnect, changed, rx));
+    Ok(watcher)
+}
+
+async fn reload_loop(
+    mut current: LoadedConfig,
+    config: Arc<RwLock<RuntimeConfig>>,
+    reconnect: Arc<Notify>,
+    changed: Arc<Notify>,
+    mut changes: mpsc::UnboundedReceiver<()>,
+) {
+    let portal_path = current.portal_path.clone();
+    let portal = current.runtime.bot.id.clone();
+
+    while changes.recv().await.is_some() {
+        tokio::time::sleep(DEBOUNCE).await;
+        while changes.try_recv().is_ok() {}
+
+        let loaded = match load_cfg(&portal_path).and_then(|loaded| check_restart_only(&current, loaded)) {
+            Ok(loaded) => loaded,
+            Err(err) => {
+                log!(INFO, "[{}] Config reload rejected, keeping the running config: {:#}", portal, err);
+                continue;
+            }
+        };
+
+        let needs_reconnect = reconnect_changes(&current, &loaded);
+        *config.write() = loaded.runtime.clone();
+        log!(INFO, "[{}] Config reloaded from {}", portal, portal_path.display());
+
+        if !needs_reconnect.is_empty() {
+            log!(INFO, "[{}] Reconnecting to apply: {}", portal, needs_reconnect.join(", "));
+            reconnect.notify_one();
+        } else if config_changed(&current, &loaded) {
+            // Only wakes a portal waiting right now, an online one has nothing to redo
+            changed.notify_waiters();
+        }
+        current = loaded;
+    }
+}
+
+/// Rejects changes that only take effect on a restart: every bot of the swarm speaks
+/// the same protocol version, chosen once at startup.
+pub fn check_restart_only(old: &LoadedConfig, new: LoadedConfig) -> Result<LoadedConfig> {
+    let (old_version, new_version) = (bot::server_version(old), bot::server_version(&new));
+    if old_version != new_version {
+        return Err(anyhow!("server.version changed from {} to {}, restart mrsbot to apply it", old_version, new_version));
+    }
+    Ok(new)
+}
+
+/// Lists the changed settings that only take effect on a new connection.
+fn reconnect_changes(old: &LoadedConfig, new: &LoadedConfig) -> Vec<&'static str> {
+    let mut changed = Vec::new();
+    if old.server != new.server {
+        changed.push("server");
+    }
+    if old.proxy != new.proxy {
+        changed.push("proxy");
+    }
+    if old.runtime.bot.nickname != new.runtime.bot.nickname {
+        changed.push("bot.nickname");
+    }
+    changed
+}
+
+/// Whether anything a stopped or parked portal could be waiting for changed, including the
+/// password read from outside the config files.
+fn config_changed(old: &LoadedConfig, new: &LoadedConfig) -> bool {
+    old.config != new.config || old.runtime.bot.password != new.runtime.bot.password
+}
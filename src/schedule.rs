@@ -0,0 +1,175 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use parking_lot::Mutex;
+use sysx::io::log::*;
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    time::Instant,
+};
+
+use crate::{
+    config::{format_duration, LoadedConfig},
+    connect,
+};
+
+/// Spreads joins out so that a multi-bot launch does not hit the server's login flow all at
+/// once: connections to one server host, and through one proxy, keep a minimum gap, and only
+/// `join.max_authenticating` bots per server host may be logging in at the same time. Portals
+/// of one host that disagree on it get the smallest value.
+#[derive(Default)]
+pub struct JoinScheduler {
+    hosts: Mutex<HashMap<String, HostSlots>>,
+    proxies: Mutex<HashMap<String, Instant>>,
+}
+
+struct HostSlots {
+    next: Instant,
+    auth: Arc<Semaphore>,
+    /// Bots that may log in at the same time, the smallest limit seen for the host.
+    limit: u32,
+}
+
+/// A reserved connection slot.
+pub struct Reservation {
+    pub at: Instant,
+    pub host: String,
+    auth: Arc<Semaphore>,
+    auth_timeout: Duration,
+}
+
+impl JoinScheduler {
+    /// Reserves the earliest slot that keeps the portal's gaps to the previous
+    /// reservations for the same server host and proxy.
+    pub fn reserve(&self, loaded: &LoadedConfig) -> Reservation {
+        let join = &loaded.runtime.join;
+        let host = server_key(loaded);
+        let proxy = proxy_key(loaded);
+        let now = Instant::now();
+
+        let mut hosts = self.hosts.lock();
+        let mut proxies = self.proxies.lock();
+        let limit = join.max_authenticating.max(1);
+        let slots = hosts.entry(host.clone()).or_insert_with(|| HostSlots {
+            next: now,
+            auth: Arc::new(Semaphore::new(limit as usize)),
+            limit,
+        });
+        if limit < slots.limit {
+            let excess = slots.limit - limit;
+            slots.limit = limit;
+            let forgotten = slots.auth.forget_permits(excess as usize) as u32;
+            if forgotten < excess {
+                // The rest is held by bots logging in, drop it once they are done
+                let auth = slots.auth.clone();
+                tokio::spawn(async move {
+                    if let Ok(permits) = auth.acquire_many_owned(excess - forgotten).await {
+                        permits.forget();
+                    }
+                });
+            }
+        }
+
+        let mut at = slots.next.max(now);
+        if let Some(proxy) = &proxy
+            && let Some(next) = proxies.get(proxy)
+        {
+            at = at.max(*next);
+        }
+        slots.next = at + join.gap;
+        if let Some(proxy) = proxy {
+            proxies.insert(proxy, at + join.proxy_gap);
+        }
+
+        Reservation {
+            at,
+            host,
+            auth: slots.auth.clone(),
+            auth_timeout: join.auth_timeout,
+        }
+    }
+}
+
+impl Reservation {
+    /// Waits for the reserved time and a free auth slot on the server host.
+    pub async fn wait(self, portal: &str) -> AuthSlot {
+        tokio::time::sleep_until(self.at).await;
+        let permit = match self.auth.clone().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                log!(INFO, "[{}] Waiting for a free auth slot on {}", portal, self.host);
+                // The semaphore is never closed
+                self.auth.acquire_owned().await.expect("auth semaphore closed")
+            }
+        };
+
+        let slot = AuthSlot(Arc::new(Mutex::new(Some(permit))));
+        let timeout = slot.clone();
+        let (portal, auth_timeout) = (portal.to_string(), self.auth_timeout);
+        tokio::spawn(async move {
+            tokio::time::sleep(auth_timeout).await;
+            if timeout.release() {
+                log!(INFO, "[{}] Still logging in after {}, freeing its auth slot", portal, format_duration(auth_timeout));
+            }
+        });
+        slot
+    }
+}
+
+/// A bot's place among the bots logging in to its server, freed once it has logged in.
+#[derive(Default, Clone)]
+pub struct AuthSlot(Arc<Mutex<Option<OwnedSemaphorePermit>>>);
+
+impl AuthSlot {
+    /// Frees the slot. Returns whether it was still held.
+    pub fn release(&self) -> bool {
+        self.0.lock().take().is_some()
+    }
+}
+
+/// Logs when each portal is scheduled to connect, relative to `start`.
+pub fn log_schedule(start: Instant, plan: &[(&str, &Reservation)]) {
+    log!(INFO, "Join schedule:");
+    let width = plan.iter().map(|(portal, _)| portal.len()).max().unwrap_or(0);
+    for (portal, reservation) in plan {
+        let offset = reservation.at.saturating_duration_since(start);
+        log!(INFO, "  +{:<8} {:width$}  {}", format!("{:.1}s", offset.as_secs_f64()), portal, reservation.host);
+    }
+}
+
+/// Server hosts are compared by the primary address, whatever it resolves to.
+fn server_key(loaded: &LoadedConfig) -> String {
+    connect::candidates(&loaded.server)
+        .ok()
+        .and_then(|candidates| candidates.into_iter().next())
+        .map(|(host, _)| host.to_lowercase())
+        .unwrap_or_default()
+}
+
+fn proxy_key(loaded: &LoadedConfig) -> Option<String> {
+    match (&loaded.proxy.host, loaded.proxy.port) {
+        (Some(host), Some(port)) => Some(format!("{}:{}", host.to_lowercase(), port)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn portal(server: &str, max_authenticating: u32) -> LoadedConfig {
+        let mut loaded = LoadedConfig::default();
+        loaded.server.host = Some(server.to_string());
+        loaded.runtime.join.max_authenticating = max_authenticating;
+        loaded
+    }
+
+    #[tokio::test]
+    async fn hosts_use_their_smallest_auth_limit() {
+        let scheduler = JoinScheduler::default();
+        let first = scheduler.reserve(&portal("mc.example.com", 3));
+        scheduler.reserve(&portal("MC.example.com", 1));
+        let other = scheduler.reserve(&portal("other.example.com", 2));
+        assert_eq!(first.auth.available_permits(), 1);
+        assert_eq!(other.auth.available_permits(), 2);
+    }
+}
@@ -0,0 +1,368 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use parking_lot::Mutex;
+use rusqlite::{params, Connection};
+
+use crate::{
+    config::{KickAction, KickCategory, KickVerdict},
+    supervisor::SessionRecord,
+};
+
+/// Database file created in the working directory unless `MRSBOT_DB` names another one.
+/// Whichever configs run, the same database is used, since every row is keyed by
+/// `server/portal`.
+pub const STORE_FILE: &str = "mrsbot.db";
+pub const STORE_ENV: &str = "MRSBOT_DB";
+
+/// Schema changes, applied in order. `PRAGMA user_version` holds how many already ran, so
+/// a migration is never changed once released; new ones are appended.
+const MIGRATIONS: &[&str] = &[
+    // 1: per-portal state
+    "CREATE TABLE sessions (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        portal TEXT NOT NULL,
+        ended_at INTEGER NOT NULL,
+        online_for_ms INTEGER,
+        reason TEXT NOT NULL,
+        category TEXT,
+        action TEXT,
+        delay_ms INTEGER,
+        rule INTEGER
+    );
+    CREATE INDEX sessions_portal ON sessions (portal, id);
+    CREATE TABLE counters (
+        portal TEXT NOT NULL,
+        name TEXT NOT NULL,
+        value INTEGER NOT NULL,
+        PRIMARY KEY (portal, name)
+    );
+    CREATE TABLE cooldowns (
+        portal TEXT NOT NULL,
+        name TEXT NOT NULL,
+        used_at INTEGER NOT NULL,
+        PRIMARY KEY (portal, name)
+    );",
+    // 2: command audit trail
+    "CREATE TABLE audit (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        portal TEXT NOT NULL,
+        at INTEGER NOT NULL,
+        player TEXT NOT NULL,
+        uuid TEXT,
+        command TEXT NOT NULL,
+        args TEXT NOT NULL,
+        allowed INTEGER NOT NULL,
+        reason TEXT NOT NULL
+    );
+    CREATE INDEX audit_portal ON audit (portal, id);",
+];
+
+/// Embedded single-file store of what must survive a restart: session history, counters,
+/// cooldowns and the command audit trail. One per process, shared by every portal.
+///
+/// The bot has no ads and no invites yet, so there are no tables for them; they come with
+/// those features, as a migration of their own.
+pub struct Store {
+    conn: Mutex<Connection>,
+    path: PathBuf,
+}
+
+impl Store {
+    /// Opens the store named by [`STORE_ENV`], or else [`STORE_FILE`].
+    pub fn open_default() -> Result<Arc<Self>> {
+        let path = std::env::var_os(STORE_ENV).map_or_else(|| PathBuf::from(STORE_FILE), PathBuf::from);
+        Self::open(&path).map(Arc::new)
+    }
+
+    /// Opens or creates the database at `path` and brings its schema up to date.
+    pub fn open(path: &Path) -> Result<Self> {
+        let conn = Connection::open(path).context(format!("Failed to open the store {}", path.display()))?;
+        conn.busy_timeout(Duration::from_secs(5))?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        migrate(&conn).context(format!("Failed to migrate the store {}", path.display()))?;
+        Ok(Self { conn: Mutex::new(conn), path: path.to_path_buf() })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The part of the store that belongs to `portal`.
+    pub fn portal(self: &Arc<Self>, portal: &str) -> PortalStore {
+        PortalStore { store: self.clone(), portal: portal.to_string() }
+    }
+}
+
+fn migrate(conn: &Connection) -> Result<()> {
+    let version: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if version > MIGRATIONS.len() {
+        anyhow::bail!("schema version {version} is newer than this build ({})", MIGRATIONS.len());
+    }
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.unchecked_transaction()?;
+        tx.execute_batch(migration).context(format!("migration {}", index + 1))?;
+        tx.pragma_update(None, "user_version", index + 1)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+/// One command someone tried to run, allowed or not.
+#[derive(Debug, Clone)]
+pub struct AuditEntry {
+    pub player: String,
+    pub uuid: Option<String>,
+    pub command: String,
+    pub args: String,
+    pub allowed: bool,
+    /// The role that allowed it, or why it was denied.
+    pub reason: String,
+}
+
+/// Store handle of one portal, kept in its bot's `State`.
+#[derive(Clone)]
+pub struct PortalStore {
+    store: Arc<Store>,
+    portal: String,
+}
+
+impl PortalStore {
+    pub fn record_session(&self, record: &SessionRecord) -> Result<()> {
+        let verdict = record.verdict.as_ref();
+        self.store.conn.lock().execute(
+            "INSERT INTO sessions (portal, ended_at, online_for_ms, reason, category, action, delay_ms, rule)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                self.portal,
+                to_unix(record.ended_at),
+                record.online_for.map(|online_for| online_for.as_millis() as i64),
+                record.reason,
+                verdict.map(|verdict| to_name(&verdict.category)).transpose()?,
+                verdict.map(|verdict| to_name(&verdict.action)).transpose()?,
+                verdict.map(|verdict| verdict.delay.as_millis() as i64),
+                verdict.and_then(|verdict| verdict.rule).map(|rule| rule as i64),
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// The last `limit` sessions, oldest first.
+    pub fn sessions(&self, limit: usize) -> Result<Vec<SessionRecord>> {
+        let conn = self.store.conn.lock();
+        let mut statement = conn.prepare(
+            "SELECT ended_at, online_for_ms, reason, category, action, delay_ms, rule FROM sessions
+             WHERE portal = ?1 ORDER BY id DESC LIMIT ?2",
+        )?;
+        let rows = statement.query_map(params![self.portal, limit as i64], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, Option<i64>>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, Option<String>>(3)?,
+                row.get::<_, Option<String>>(4)?,
+                row.get::<_, Option<i64>>(5)?,
+                row.get::<_, Option<i64>>(6)?,
+            ))
+        })?;
+
+        let mut records = Vec::new();
+        for row in rows {
+            let (ended_at, online_for, reason, category, action, delay, rule) = row?;
+            let verdict = match (category, action) {
+                (Some(category), Some(action)) => Some(KickVerdict {
+                    category: from_name::<KickCategory>(&category)?,
+                    action: from_name::<KickAction>(&action)?,
+                    delay: Duration::from_millis(delay.unwrap_or_default() as u64),
+                    rule: rule.map(|rule| rule as usize),
+                }),
+                _ => None,
+            };
+            records.push(SessionRecord {
+                ended_at: from_unix(ended_at),
+                online_for: online_for.map(|online_for| Duration::from_millis(online_for as u64)),
+                reason,
+                verdict,
+            });
+        }
+        records.reverse();
+        Ok(records)
+    }
+
+    pub fn audit(&self, entry: &AuditEntry) -> Result<()> {
+        self.store.conn.lock().execute(
+            "INSERT INTO audit (portal, at, player, uuid, command, args, allowed, reason)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                self.portal,
+                to_unix(SystemTime::now()),
+                entry.player,
+                entry.uuid,
+                entry.command,
+                entry.args,
+                entry.allowed,
+                entry.reason,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Every counter of the portal by name.
+    pub fn counters(&self) -> Result<BTreeMap<String, u64>> {
+        let conn = self.store.conn.lock();
+        let mut statement = conn.prepare("SELECT name, value FROM counters WHERE portal = ?1")?;
+        let rows = statement.query_map(params![self.portal], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)? as u64))
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Sets every `(name, value)` counter in a single transaction.
+    pub fn set_counters(&self, values: &[(&str, u64)]) -> Result<()> {
+        let mut conn = self.store.conn.lock();
+        let tx = conn.transaction()?;
+        for (name, value) in values {
+            tx.execute(
+                "INSERT INTO counters (portal, name, value) VALUES (?1, ?2, ?3)
+                 ON CONFLICT (portal, name) DO UPDATE SET value = excluded.value",
+                params![self.portal, name, *value as i64],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// When each cooldown of the portal was last used, by name.
+    pub fn cooldowns(&self) -> Result<HashMap<String, SystemTime>> {
+        let conn = self.store.conn.lock();
+        let mut statement = conn.prepare("SELECT name, used_at FROM cooldowns WHERE portal = ?1")?;
+        let rows = statement.query_map(params![self.portal], |row| {
+            Ok((row.get::<_, String>(0)?, from_unix(row.get(1)?)))
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    pub fn set_cooldown(&self, name: &str, used_at: SystemTime) -> Result<()> {
+        self.store.conn.lock().execute(
+            "INSERT INTO cooldowns (portal, name, used_at) VALUES (?1, ?2, ?3)
+             ON CONFLICT (portal, name) DO UPDATE SET used_at = excluded.used_at",
+            params![self.portal, name, to_unix(used_at)],
+        )?;
+        Ok(())
+    }
+}
+
+fn to_unix(at: SystemTime) -> i64 {
+    at.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs() as i64)
+}
+
+fn from_unix(secs: i64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs.max(0) as u64)
+}
+
+/// Stored as their config names, e.g. `server_restart`.
+fn to_name<T: serde::Serialize>(value: &T) -> Result<String> {
+    match serde_json::to_value(value)? {
+        serde_json::Value::String(name) => Ok(name),
+        other => anyhow::bail!("unexpected value {other}"),
+    }
+}
+
+fn from_name<T: serde::de::DeserializeOwned>(name: &str) -> rusqlite::Result<T> {
+    serde_json::from_value(serde_json::Value::String(name.to_string()))
+        .map_err(|err| rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::scratch_dir;
+
+    fn user_version(conn: &Connection) -> usize {
+        conn.pragma_query_value(None, "user_version", |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn upgrades_an_older_schema_and_keeps_its_rows() {
+        let dir = scratch_dir("store-migrate");
+        let path = dir.join(STORE_FILE);
+        {
+            let conn = Connection::open(&path).unwrap();
+            conn.execute_batch(MIGRATIONS[0]).unwrap();
+            conn.pragma_update(None, "user_version", 1).unwrap();
+            conn.execute("INSERT INTO counters (portal, name, value) VALUES ('mw/s1', 'sessions', 7)", []).unwrap();
+        }
+
+        let store = Arc::new(Store::open(&path).unwrap());
+        assert_eq!(user_version(&store.conn.lock()), MIGRATIONS.len());
+        let portal = store.portal("mw/s1");
+        assert_eq!(portal.counters().unwrap().get("sessions"), Some(&7));
+        portal.audit(&AuditEntry {
+            player: "Owner".to_string(),
+            uuid: None,
+            command: "status".to_string(),
+            args: String::new(),
+            allowed: true,
+            reason: "owner".to_string(),
+        }).unwrap();
+        drop(store);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_a_newer_schema() {
+        let dir = scratch_dir("store-newer");
+        let path = dir.join(STORE_FILE);
+        Connection::open(&path).unwrap().pragma_update(None, "user_version", MIGRATIONS.len() + 1).unwrap();
+        let err = format!("{:#}", Store::open(&path).err().unwrap());
+        assert!(err.contains("is newer than this build"), "{err}");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn sessions_and_counters_are_kept_per_portal() {
+        let dir = scratch_dir("store-portals");
+        let path = dir.join(STORE_FILE);
+        let store = Arc::new(Store::open(&path).unwrap());
+        let (mw, mb) = (store.portal("mw/s1"), store.portal("mb/s1"));
+
+        mw.set_counters(&[("sessions", 1), ("spawns", 2)]).unwrap();
+        mw.set_counters(&[("sessions", 2)]).unwrap();
+        mb.set_counters(&[("sessions", 1)]).unwrap();
+        assert_eq!(mw.counters().unwrap(), BTreeMap::from([("sessions".to_string(), 2), ("spawns".to_string(), 2)]));
+
+        let used_at = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        mw.set_cooldown("command:say", UNIX_EPOCH).unwrap();
+        mw.set_cooldown("command:say", used_at).unwrap();
+        assert_eq!(mw.cooldowns().unwrap(), HashMap::from([("command:say".to_string(), used_at)]));
+        assert!(mb.cooldowns().unwrap().is_empty());
+
+        let verdict = KickVerdict {
+            category: KickCategory::ServerRestart,
+            action: KickAction::Delay,
+            delay: Duration::from_secs(60),
+            rule: Some(0),
+        };
+        for reason in ["first", "second", "third"] {
+            mw.record_session(&SessionRecord {
+                ended_at: SystemTime::now(),
+                online_for: Some(Duration::from_secs(90)),
+                reason: reason.to_string(),
+                verdict: Some(verdict.clone()),
+            }).unwrap();
+        }
+        let sessions = mw.sessions(2).unwrap();
+        assert_eq!(sessions.iter().map(|record| record.reason.as_str()).collect::<Vec<_>>(), ["second", "third"]);
+        assert_eq!(sessions[1].verdict.as_ref(), Some(&verdict));
+        assert_eq!(sessions[1].online_for, Some(Duration::from_secs(90)));
+        assert!(mb.sessions(10).unwrap().is_empty());
+
+        drop((store, mw, mb));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,289 @@
+use std::{
+    collections::{hash_map::RandomState, BTreeMap, HashMap, VecDeque},
+    fmt,
+    hash::{BuildHasher, Hasher},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use parking_lot::RwLock;
+use sysx::io::log::*;
+use tokio::{sync::mpsc, time::Instant};
+
+use crate::config::{format_duration, KickVerdict, RestartResolved};
+
+/// Where a supervised portal currently is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortalStatus {
+    Connecting,
+    Online,
+    /// Waiting to retry after a failure.
+    BackingOff { until: Instant },
+    /// Failed too often, waiting for a trial join or a config change.
+    Parked { until: Instant },
+    /// Stopped by a kick rule, waiting for a config change.
+    Stopped,
+}
+
+impl PortalStatus {
+    fn label(&self) -> &'static str {
+        match self {
+            PortalStatus::Connecting => "connecting",
+            PortalStatus::Online => "online",
+            PortalStatus::BackingOff { .. } => "backing off",
+            PortalStatus::Parked { .. } => "parked",
+            PortalStatus::Stopped => "stopped",
+        }
+    }
+}
+
+impl fmt::Display for PortalStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PortalStatus::BackingOff { until } | PortalStatus::Parked { until } => {
+                let left = until.saturating_duration_since(Instant::now());
+                write!(f, "{} (retry in {})", self.label(), format_duration(left))
+            }
+            _ => write!(f, "{}", self.label()),
+        }
+    }
+}
+
+/// How many ended sessions are kept per portal.
+pub const SESSION_HISTORY: usize = 20;
+
+/// One ended session of a portal and why it ended.
+#[derive(Debug, Clone)]
+pub struct SessionRecord {
+    pub ended_at: SystemTime,
+    /// How long the bot was logged in, `None` if it never got that far.
+    pub online_for: Option<Duration>,
+    pub reason: String,
+    /// Classification of the disconnect reason, `None` when the bot failed to join.
+    pub verdict: Option<KickVerdict>,
+}
+
+/// Status and recent sessions of every portal of the process.
+#[derive(Default, Clone)]
+pub struct StatusBoard {
+    statuses: Arc<RwLock<BTreeMap<String, PortalStatus>>>,
+    sessions: Arc<RwLock<HashMap<String, VecDeque<SessionRecord>>>>,
+}
+
+impl StatusBoard {
+    pub fn set(&self, portal: &str, status: PortalStatus) {
+        self.statuses.write().insert(portal.to_string(), status);
+        log!(INFO, "[{}] Status: {} ({})", portal, status, self.summary());
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<String, PortalStatus> {
+        self.statuses.read().clone()
+    }
+
+    pub fn record_session(&self, portal: &str, record: SessionRecord) {
+        let mut sessions = self.sessions.write();
+        let history = sessions.entry(portal.to_string()).or_default();
+        if history.len() == SESSION_HISTORY {
+            history.pop_front();
+        }
+        history.push_back(record);
+    }
+
+    /// Replaces the session history of the portal, e.g. with the one saved before a restart.
+    pub fn restore_sessions(&self, portal: &str, records: Vec<SessionRecord>) {
+        let skip = records.len().saturating_sub(SESSION_HISTORY);
+        self.sessions.write().insert(portal.to_string(), records.into_iter().skip(skip).collect());
+    }
+
+    /// Recent sessions of the portal, oldest first.
+    pub fn sessions(&self, portal: &str) -> Vec<SessionRecord> {
+        self.sessions.read().get(portal).map(|history| history.iter().cloned().collect()).unwrap_or_default()
+    }
+
+    /// Portal counts per status, e.g. `3 online, 1 backing off`.
+    pub fn summary(&self) -> String {
+        let mut counts = BTreeMap::new();
+        for status in self.statuses.read().values() {
+            *counts.entry(status.label()).or_insert(0) += 1;
+        }
+        counts.iter().map(|(label, count)| format!("{count} {label}")).collect::<Vec<_>>().join(", ")
+    }
+
+    /// One line per portal with its status and how its last session ended.
+    pub fn report(&self) -> Vec<String> {
+        self.snapshot()
+            .into_iter()
+            .map(|(portal, status)| match self.sessions(&portal).last() {
+                Some(last) => {
+                    let ago = last.ended_at.elapsed().unwrap_or_default();
+                    format!("{portal}: {status}, last session ended {} ago: {}", format_duration(ago), last.reason)
+                }
+                None => format!("{portal}: {status}"),
+            })
+            .collect()
+    }
+}
+
+/// What a bot reports to its supervisor from the event handlers.
+#[derive(Debug)]
+pub enum BotSignal {
+    Online,
+    Disconnected(String),
+    /// A new connection was asked for, e.g. by the `reconnect` command.
+    Reconnect(String),
+}
+
+/// Sender half kept in the bot's `State`. A default one (no supervisor) drops every signal.
+#[derive(Default, Clone)]
+pub struct Signals(Option<mpsc::UnboundedSender<BotSignal>>);
+
+impl Signals {
+    pub fn channel() -> (Self, mpsc::UnboundedReceiver<BotSignal>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        (Self(Some(tx)), rx)
+    }
+
+    pub fn send(&self, signal: BotSignal) {
+        if let Some(tx) = &self.0 {
+            let _ = tx.send(signal);
+        }
+    }
+}
+
+/// Exponential backoff with a retry budget. Once the budget is spent the circuit opens and the
+/// portal is parked; after parking it gets a single trial join, and failing that parks again.
+#[derive(Debug, Default)]
+pub struct Backoff {
+    failures: u32,
+    trial: bool,
+}
+
+pub enum Retry {
+    After(Duration),
+    Park(Duration),
+}
+
+impl Backoff {
+    /// Records a failure and decides how long to wait before the next join.
+    pub fn failed(&mut self, restart: &RestartResolved) -> Retry {
+        self.failures += 1;
+        if self.trial || self.failures > restart.max_retries {
+            self.trial = true;
+            return Retry::Park(restart.park_for);
+        }
+
+        let exponent = (self.failures - 1).min(31);
+        let delay = restart.initial_delay.saturating_mul(1 << exponent).min(restart.max_delay);
+        Retry::After(jittered(delay, restart.jitter))
+    }
+
+    /// Failures in a row so far.
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    /// A stable session or a config change starts over with a full budget.
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Spreads `delay` randomly by up to `jitter` of it in either direction.
+fn jittered(delay: Duration, jitter: f64) -> Duration {
+    let random = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+    delay.mul_f64((1.0 + jitter * (random * 2.0 - 1.0)).max(0.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn restart() -> RestartResolved {
+        RestartResolved {
+            initial_delay: Duration::from_secs(5),
+            max_delay: Duration::from_secs(60),
+            jitter: 0.0,
+            max_retries: 5,
+            park_for: Duration::from_secs(600),
+            stable_after: Duration::from_secs(300),
+        }
+    }
+
+    fn delay(retry: Retry) -> Option<Duration> {
+        match retry {
+            Retry::After(delay) => Some(delay),
+            Retry::Park(_) => None,
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let restart = restart();
+        let mut backoff = Backoff::default();
+        let delays: Vec<_> = (0..5).map(|_| delay(backoff.failed(&restart)).unwrap().as_secs()).collect();
+        assert_eq!(delays, [5, 10, 20, 40, 60]);
+        assert_eq!(backoff.failures(), 5);
+    }
+
+    #[test]
+    fn backoff_parks_once_the_budget_is_spent_and_after_a_failed_trial() {
+        let restart = restart();
+        let mut backoff = Backoff::default();
+        for _ in 0..restart.max_retries {
+            assert!(delay(backoff.failed(&restart)).is_some());
+        }
+        assert!(matches!(backoff.failed(&restart), Retry::Park(park) if park == restart.park_for));
+        // The trial join after parking gets no budget of its own
+        assert!(matches!(backoff.failed(&restart), Retry::Park(_)));
+
+        backoff.reset();
+        assert_eq!(backoff.failures(), 0);
+        assert_eq!(delay(backoff.failed(&restart)), Some(restart.initial_delay));
+    }
+
+    #[test]
+    fn jitter_stays_within_bounds() {
+        for _ in 0..100 {
+            let delay = jittered(Duration::from_secs(100), 0.2);
+            assert!(delay >= Duration::from_secs(80) && delay <= Duration::from_secs(120), "{delay:?}");
+        }
+    }
+
+    #[test]
+    fn status_board_counts_and_reports_portals() {
+        let status = StatusBoard::default();
+        status.set("mw/s1", PortalStatus::Online);
+        status.set("mw/s2", PortalStatus::Online);
+        status.set("mb/s1", PortalStatus::Stopped);
+        assert_eq!(status.summary(), "2 online, 1 stopped");
+
+        status.record_session("mb/s1", SessionRecord {
+            ended_at: SystemTime::now(),
+            online_for: None,
+            reason: "banned".to_string(),
+            verdict: None,
+        });
+        let report = status.report();
+        assert_eq!(report.len(), 3);
+        assert!(report[0].starts_with("mb/s1: stopped, last session ended ") && report[0].ends_with(": banned"), "{}", report[0]);
+        assert_eq!(report[1], "mw/s1: online");
+    }
+
+    #[test]
+    fn session_history_keeps_the_latest() {
+        let status = StatusBoard::default();
+        let record = |n: usize| SessionRecord {
+            ended_at: SystemTime::now(),
+            online_for: None,
+            reason: n.to_string(),
+            verdict: None,
+        };
+        status.restore_sessions("mw/s1", (0..SESSION_HISTORY + 5).map(record).collect());
+        status.record_session("mw/s1", record(100));
+
+        let sessions = status.sessions("mw/s1");
+        assert_eq!(sessions.len(), SESSION_HISTORY);
+        assert_eq!(sessions[0].reason, "6");
+        assert_eq!(sessions.last().unwrap().reason, "100");
+    }
+}
@@ -0,0 +1,386 @@
+use std::{
+    collections::BTreeSet,
+    path::Path,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{anyhow, Context, Result};
+use azalea::{ecs::prelude::Resource, prelude::*, swarm::prelude::*};
+use azalea_viaversion::ViaVersionPlugin;
+use parking_lot::{Mutex, RwLock};
+use sysx::io::log::*;
+use tokio::{
+    sync::{mpsc, Notify},
+    time::Instant,
+};
+
+use crate::{
+    bot::{self, JoinTarget},
+    config::{discover_portals, format_duration, load_cfg, KickAction, LoadedConfig, RuntimeConfig},
+    connect,
+    handler::handle,
+    reload,
+    schedule::{log_schedule, JoinScheduler, Reservation},
+    store::{PortalStore, Store},
+    supervisor::{Backoff, BotSignal, PortalStatus, Retry, SessionRecord, Signals, StatusBoard, SESSION_HISTORY},
+    types::State,
+};
+
+/// How often the status of every portal is logged.
+const STATUS_REPORT_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+/// One portal bot of the swarm and the config it runs with.
+pub struct PortalBot {
+    loaded: LoadedConfig,
+    /// Shared with the bot's `State` and the config watcher.
+    config: Arc<RwLock<RuntimeConfig>>,
+    reconnect: Arc<Notify>,
+    /// Any other config change, wakes the portal while stopped or parked.
+    changed: Arc<Notify>,
+    /// Slot planned at startup, later joins reserve a new one.
+    reservation: Option<Reservation>,
+    store: PortalStore,
+}
+
+impl PortalBot {
+    fn name(&self) -> &str {
+        &self.loaded.runtime.bot.id
+    }
+
+    /// Reloads the portal config before a reconnect, keeping the previous one if it is invalid.
+    fn reload(&mut self) {
+        match load_cfg(&self.loaded.portal_path).and_then(|reloaded| reload::check_restart_only(&self.loaded, reloaded)) {
+            Ok(reloaded) => self.loaded = reloaded,
+            Err(err) => log!(INFO, "[{}] Failed to reload config, reconnecting with the previous one: {:#}", self.name(), err),
+        }
+    }
+}
+
+#[derive(Default, Clone, Resource)]
+pub struct SwarmState {
+    /// Portals waiting to be joined, taken by the first `SwarmEvent::Init`.
+    pending: Arc<Mutex<Vec<PortalBot>>>,
+    scheduler: Arc<JoinScheduler>,
+    status: StatusBoard,
+}
+
+/// Runs every portal found under `root` (a portal file, a server directory or the whole
+/// config tree) as supervised bots of a single swarm. Each bot keeps its own `State`, config watcher and log prefix.
+pub async fn run_swarm(root: &Path) -> Result<()> {
+    let paths = discover_portals(root)?;
+    if paths.is_empty() {
+        return Err(anyhow!("No portal configs found in {}", root.display()));
+    }
+
+    let mut portals = Vec::new();
+    let mut failed = 0;
+    for path in &paths {
+        match load_cfg(path) {
+            Ok(loaded) => portals.push(loaded),
+            Err(err) => {
+                eprintln!("FAIL  {}: {err:#}", path.display());
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(anyhow!("{} of {} portal config(s) failed to load", failed, paths.len()));
+    }
+
+    // ViaVersion is a plugin of the whole swarm, so all bots must speak the same version
+    let versions: BTreeSet<String> = portals.iter().map(bot::server_version).collect();
+    if versions.len() > 1 {
+        let versions: Vec<_> = versions.into_iter().collect();
+        return Err(anyhow!(
+            "Portals in {} use different server versions ({}), run them separately",
+            root.display(),
+            versions.join(", ")
+        ));
+    }
+    let version = versions.into_iter().next().unwrap_or_else(|| bot::AUTO_VERSION.to_string());
+
+    let (host, port) = connect::candidates(&portals[0].server)?.remove(0);
+    let default_address = format!("{host}:{}", port.unwrap_or(connect::DEFAULT_PORT));
+
+    let store = Store::open_default()?;
+    log!(INFO, "Using store {}", store.path().display());
+    let status = StatusBoard::default();
+
+    let scheduler = Arc::new(JoinScheduler::default());
+    let start = tokio::time::Instant::now();
+    let mut watchers = Vec::new();
+    let mut pending = Vec::new();
+    for loaded in portals {
+        let config = Arc::new(RwLock::new(loaded.runtime.clone()));
+        let reconnect = Arc::new(Notify::new());
+        let changed = Arc::new(Notify::new());
+        watchers.push(reload::watch_config(&loaded, config.clone(), reconnect.clone(), changed.clone())?);
+        let reservation = Some(scheduler.reserve(&loaded));
+        let store = store.portal(&loaded.runtime.bot.id);
+        let sessions = store.sessions(SESSION_HISTORY)
+            .context(format!("Failed to load the sessions of {}", loaded.runtime.bot.id))?;
+        status.restore_sessions(&loaded.runtime.bot.id, sessions);
+        pending.push(PortalBot { loaded, config, reconnect, changed, reservation, store });
+    }
+    log!(INFO, "Starting {} portal bot(s) from {}", pending.len(), root.display());
+    let plan: Vec<_> = pending
+        .iter()
+        .filter_map(|portal| Some((portal.name(), portal.reservation.as_ref()?)))
+        .collect();
+    log_schedule(start, &plan);
+
+    let mut swarm_builder = SwarmBuilder::new();
+    if version.as_str() != bot::AUTO_VERSION {
+        let via_version_plugin = ViaVersionPlugin::start(version).await;
+        swarm_builder = swarm_builder.add_plugins(via_version_plugin);
+    }
+
+    let swarm_state = SwarmState {
+        pending: Arc::new(Mutex::new(pending)),
+        scheduler,
+        status,
+    };
+    swarm_builder
+        .set_handler(handle)
+        // Restarts are up to the supervisor
+        .reconnect_after(None)
+        .set_swarm_handler(swarm_handle)
+        .set_swarm_state(swarm_state)
+        .start(default_address.as_str())
+        .await?
+}
+
+async fn swarm_handle(swarm: Swarm, event: SwarmEvent, state: SwarmState) -> Result<()> {
+    if let SwarmEvent::Init = event {
+        for portal in state.pending.lock().drain(..) {
+            tokio::spawn(supervise(swarm.clone(), state.scheduler.clone(), state.status.clone(), portal));
+        }
+        tokio::spawn(report_status(state.status.clone()));
+    }
+    Ok(())
+}
+
+/// Logs the status of every portal every [`STATUS_REPORT_INTERVAL`].
+async fn report_status(status: StatusBoard) {
+    let mut interval = tokio::time::interval(STATUS_REPORT_INTERVAL);
+    // The first tick completes right away, before any portal joined
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        log!(INFO, "Status: {}", status.summary());
+        for line in status.report() {
+            log!(INFO, "  {}", line);
+        }
+    }
+}
+
+/// How a session ended.
+enum SessionEnd {
+    /// The bot could not join at all.
+    JoinFailed(String),
+    /// The server disconnected the bot.
+    Disconnected { reason: String, online_for: Option<Duration> },
+    /// The config changed in a way that needs a new connection, or the bot asked for one.
+    Reload,
+}
+
+/// Keeps one portal bot in the swarm. Disconnect reasons are classified by the portal's
+/// kick rules, which decide whether to rejoin now, after a delay, with the usual backoff or
+/// not at all. Backoff parks the portal once the retry budget is spent, and a config change
+/// that needs a new connection always rejoins right away.
+async fn supervise(swarm: Swarm, scheduler: Arc<JoinScheduler>, status: StatusBoard, mut portal: PortalBot) {
+    let mut backoff = Backoff::default();
+    loop {
+        status.set(portal.name(), PortalStatus::Connecting);
+        let client = Arc::new(Mutex::new(None));
+        let (signals, mut events) = Signals::channel();
+        let reservation = match portal.reservation.take() {
+            Some(reservation) => reservation,
+            None => scheduler.reserve(&portal.loaded),
+        };
+
+        let (end, state) = match join(&swarm, &portal, reservation, client.clone(), signals).await {
+            Ok(state) => (watch_session(&portal, &status, &mut events).await, Some(state)),
+            Err(err) => (SessionEnd::JoinFailed(format!("failed to join: {err:#}")), None),
+        };
+        if let Some(bot) = client.lock().take() {
+            bot.disconnect();
+        }
+        if let Some(state) = state {
+            save_counters(&portal, &state);
+        }
+        // The config watcher only updates the running bot, the kick rules and restart policy
+        // that judge this session come from disk
+        portal.reload();
+
+        let (reason, online_for, verdict) = match end {
+            SessionEnd::Reload => {
+                backoff.reset();
+                continue;
+            }
+            SessionEnd::JoinFailed(reason) => (reason, None, None),
+            SessionEnd::Disconnected { reason, online_for } => {
+                let verdict = portal.loaded.kick.classify(&reason);
+                (reason, online_for, Some(verdict))
+            }
+        };
+        let record = SessionRecord {
+            ended_at: SystemTime::now(),
+            online_for,
+            reason: reason.clone(),
+            verdict: verdict.clone(),
+        };
+        if let Err(err) = portal.store.record_session(&record) {
+            log!(INFO, "[{}] Failed to save the session: {:#}", portal.name(), err);
+        }
+        status.record_session(portal.name(), record);
+
+        let restart = portal.loaded.runtime.restart.clone();
+        let action = verdict.as_ref().map_or(KickAction::Backoff, |verdict| verdict.action);
+        if let Some(verdict) = &verdict {
+            log!(INFO, "[{}] Kicked ({}, action: {}): {}", portal.name(), verdict.category, verdict.action, reason);
+        }
+        let (wait, next) = match action {
+            KickAction::Reconnect => continue,
+            KickAction::Delay => {
+                let delay = verdict.as_ref().map_or(restart.initial_delay, |verdict| verdict.delay);
+                log!(INFO, "[{}] Rejoining in {}", portal.name(), format_duration(delay));
+                (Some(delay), PortalStatus::BackingOff { until: Instant::now() + delay })
+            }
+            KickAction::Stop => {
+                log!(INFO, "[{}] Stopped until its config changes", portal.name());
+                (None, PortalStatus::Stopped)
+            }
+            KickAction::Backoff | KickAction::Alert => {
+                if action == KickAction::Alert {
+                    log!(INFO, "[{}] ALERT: {}", portal.name(), reason);
+                }
+                // A session that stayed up long enough was a success, start over
+                if online_for.is_some_and(|online_for| online_for >= restart.stable_after) {
+                    backoff.reset();
+                }
+                match backoff.failed(&restart) {
+                    Retry::After(delay) => {
+                        log!(INFO, "[{}] Restarting in {} (failure {}/{}): {}",
+                            portal.name(), format_duration(delay), backoff.failures(), restart.max_retries, reason);
+                        (Some(delay), PortalStatus::BackingOff { until: Instant::now() + delay })
+                    }
+                    Retry::Park(delay) => {
+                        log!(INFO, "[{}] Parked after {} failure(s) in a row, trying again in {}: {}",
+                            portal.name(), backoff.failures(), format_duration(delay), reason);
+                        (Some(delay), PortalStatus::Parked { until: Instant::now() + delay })
+                    }
+                }
+            }
+        };
+
+        status.set(portal.name(), next);
+        let sleep = async {
+            match wait {
+                Some(wait) => tokio::time::sleep(wait).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            _ = sleep => {}
+            _ = portal.reconnect.notified() => {
+                backoff.reset();
+                portal.reload();
+            }
+            _ = portal.changed.notified() => {
+                log!(INFO, "[{}] Config changed, rejoining", portal.name());
+                backoff.reset();
+                portal.reload();
+            }
+        }
+    }
+}
+
+async fn watch_session(
+    portal: &PortalBot,
+    status: &StatusBoard,
+    events: &mut mpsc::UnboundedReceiver<BotSignal>,
+) -> SessionEnd {
+    let mut online_since: Option<Instant> = None;
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Some(BotSignal::Online) => {
+                    online_since = Some(Instant::now());
+                    status.set(portal.name(), PortalStatus::Online);
+                }
+                Some(BotSignal::Reconnect(why)) => {
+                    log!(INFO, "[{}] Reconnecting: {}", portal.name(), why);
+                    return SessionEnd::Reload;
+                }
+                Some(BotSignal::Disconnected(reason)) => {
+                    let online_for = online_since.map(|since| since.elapsed());
+                    return SessionEnd::Disconnected { reason, online_for };
+                }
+                // Every sender lives in the bot's State, so the bot is gone
+                None => return SessionEnd::JoinFailed("bot was removed".to_string()),
+            },
+            _ = portal.reconnect.notified() => return SessionEnd::Reload,
+        }
+    }
+}
+
+/// Saves the portal's counters once a session is over.
+fn save_counters(portal: &PortalBot, state: &State) {
+    let counters = state.session.read().counters.clone();
+    if let Err(err) = portal.store.set_counters(&counters.to_stored()) {
+        log!(INFO, "[{}] Failed to save counters: {:#}", portal.name(), err);
+    }
+    log!(
+        INFO,
+        "[{}] Totals: {} session(s), {} spawn(s), {} chat line(s), {} tick(s)",
+        portal.name(),
+        counters.sessions,
+        counters.spawn,
+        counters.chat,
+        counters.ticks
+    );
+}
+
+/// Joins the portal's bot, returning its state.
+async fn join(
+    swarm: &Swarm,
+    portal: &PortalBot,
+    reservation: Reservation,
+    client: Arc<Mutex<Option<Client>>>,
+    signals: Signals,
+) -> Result<State> {
+    let auth = reservation.wait(portal.name()).await;
+    let state = bot::initial_state(portal.config.clone(), client.clone(), auth.clone(), signals, Some(portal.store.clone()));
+    let joined = add_bot(swarm, &portal.loaded, state.clone()).await;
+    if joined.is_err() {
+        // A bot that never joined will not log in either
+        auth.release();
+    }
+    let (target, bot) = joined?;
+    log!(INFO, "[{}] Joined {} as {}", portal.name(), target.address, target.account.username);
+    *client.lock() = Some(bot);
+    Ok(state)
+}
+
+/// Joins through the first target that accepts the connection within `server.timeout`.
+async fn add_bot(swarm: &Swarm, loaded: &LoadedConfig, state: State) -> Result<(JoinTarget, Client)> {
+    let mut targets = bot::join_targets(loaded).await?.into_iter().peekable();
+    while let Some(target) = targets.next() {
+        let options = target.swarm_options()?;
+        let joining = swarm.add_with_opts(&target.account, state.clone(), &options);
+        let joined = match tokio::time::timeout(target.timeout, joining).await {
+            Ok(joined) => joined.map_err(anyhow::Error::from),
+            Err(_) => Err(anyhow!("timed out after {}", format_duration(target.timeout))),
+        };
+        match joined {
+            Ok(bot) => return Ok((target, bot)),
+            Err(err) if targets.peek().is_some() => {
+                log!(INFO, "[{}] Failed to join {}, trying the next address: {}", loaded.runtime.bot.id, target.address, err);
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    Err(anyhow!("No server address to join"))
+}
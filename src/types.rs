@@ -1,21 +1,177 @@
-use crate::config::RuntimeConfig;
-use azalea::{ecs::component::Component, Vec3};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
+use crate::{
+    auth::{AuthFlow, AuthState},
+    config::RuntimeConfig,
+    navigation::{Navigation, Stage},
+    outbox::Outbox,
+    schedule::AuthSlot,
+    store::PortalStore,
+    supervisor::Signals,
+};
+use azalea::{ecs::component::Component, Client, Vec3};
+use parking_lot::{Mutex, RwLock};
+use sysx::io::log::*;
+use tokio::time::Instant;
+
+/// Per-bot state. Azalea hands every event its own clone, so everything that changes while
+/// the bot runs sits behind an `Arc` and is shared by all the clones.
 #[derive(Default, Clone, Component)]
 pub struct State {
-    pub config: RuntimeConfig,
+    /// Shared with the config watcher, which swaps it on reload.
+    pub config: Arc<RwLock<RuntimeConfig>>,
+    /// The running client, set on `Event::Init`. Used to disconnect it for a controlled reconnect.
+    pub client: Arc<Mutex<Option<Client>>>,
+    /// Held while logging in, see `JoinScheduler`.
+    pub auth_slot: AuthSlot,
+    /// Progress of the login flow.
+    pub auth: Arc<Mutex<AuthFlow>>,
+    /// Progress from the hub to the warp.
+    pub nav: Arc<Mutex<Navigation>>,
+    /// Everything the bot says goes through it, see `outbox::send`.
+    pub outbox: Arc<Mutex<Outbox>>,
+    /// Reports login and disconnect to the portal's supervisor.
+    pub signals: Signals,
+    /// What outlives the process, `None` when running without a store.
+    pub store: Option<PortalStore>,
+    /// Counters, flags and positions of the session, see [`State::update`].
+    pub session: Arc<RwLock<Session>>,
+}
+
+impl State {
+    /// Changes the session data under its lock. Keep `f` short, every event shares it.
+    pub fn update<R>(&self, f: impl FnOnce(&mut Session) -> R) -> R {
+        f(&mut self.session.write())
+    }
+
+    /// Marks the cooldown `name` used now, unless it was used less than `cooldown` ago;
+    /// returns the time left then. Uses are saved, so a restart keeps every cooldown.
+    pub fn try_use(&self, name: &str, cooldown: Duration) -> Option<Duration> {
+        let now = Instant::now();
+        let left = self.update(|session| {
+            if let Some(last) = session.cooldowns.get(name) {
+                let ready = *last + cooldown;
+                if ready > now {
+                    return Some(ready - now);
+                }
+            }
+            session.cooldowns.insert(name.to_string(), now);
+            None
+        });
+        if left.is_none()
+            && !cooldown.is_zero()
+            && let Some(store) = &self.store
+            && let Err(err) = store.set_cooldown(name, SystemTime::now())
+        {
+            log!(INFO, "[{}] Failed to save the cooldown {}: {:#}", self.config.read().bot.id, name, err);
+        }
+        left
+    }
+
+    /// A consistent copy of what the bot is doing, safe to keep and read from anywhere.
+    pub fn snapshot(&self) -> StateSnapshot {
+        let (auth, stage, warp_position) = {
+            let auth = self.auth.lock().state();
+            let nav = self.nav.lock();
+            (auth, nav.stage(), nav.warp_position())
+        };
+        StateSnapshot {
+            portal: self.config.read().bot.id.clone(),
+            auth,
+            stage,
+            warp_position,
+            session: self.session.read().clone(),
+        }
+    }
+}
+
+/// What changes from event to event during one connection.
+#[derive(Debug, Default, Clone)]
+pub struct Session {
     pub counters: Counters,
     pub flags: Flags,
+    /// Position on the previous tick.
     pub prev_pos: Vec3,
+    /// When each cooldown was last used, see [`State::try_use`].
+    pub cooldowns: HashMap<String, Instant>,
+    /// When each player, by lowercase nickname, was last told a command was denied.
+    pub denials: HashMap<String, Instant>,
+}
+
+impl Session {
+    /// A new session of the portal, carrying on with its saved counters and cooldowns.
+    pub fn restore(store: &PortalStore) -> anyhow::Result<Self> {
+        let mut counters = Counters::from_stored(&store.counters()?);
+        counters.sessions += 1;
+        let now = (SystemTime::now(), Instant::now());
+        let cooldowns = store
+            .cooldowns()?
+            .into_iter()
+            // Too old for an `Instant` means long over
+            .filter_map(|(name, used_at)| Some((name, now.1.checked_sub(now.0.duration_since(used_at).ok()?)?)))
+            .collect();
+        Ok(Self { counters, cooldowns, ..Default::default() })
+    }
 }
 
-#[derive(Default, Clone)]
+/// What the portal counted over every run, restored when the bot joins and saved when it
+/// leaves.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub struct Counters {
-    pub spawn: i32, 
+    pub sessions: u64,
+    pub spawn: u64,
+    pub chat: u64,
+    pub ticks: u64,
 }
 
-#[derive(Default, Clone)]
+impl Counters {
+    /// Names of the counters in the store.
+    const NAMES: [&'static str; 4] = ["sessions", "spawns", "chat_messages", "ticks"];
+
+    pub fn from_stored(values: &BTreeMap<String, u64>) -> Self {
+        let [sessions, spawn, chat, ticks] = Self::NAMES.map(|name| values.get(name).copied().unwrap_or_default());
+        Self { sessions, spawn, chat, ticks }
+    }
+
+    pub fn to_stored(&self) -> [(&'static str, u64); 4] {
+        let [sessions, spawns, chat, ticks] = Self::NAMES;
+        [(sessions, self.sessions), (spawns, self.spawn), (chat, self.chat), (ticks, self.ticks)]
+    }
+}
+
+#[derive(Debug, Default, Clone)]
 pub struct Flags {
     pub init: bool,
     pub login: bool,
+    /// Set by the `pause` command: no chat reactions and no warp enforcement.
+    pub paused: bool,
+}
+
+/// Read-only copy of a bot's state, see [`State::snapshot`].
+#[derive(Debug, Clone)]
+pub struct StateSnapshot {
+    /// `server/portal`, see [`crate::config::BotConfigResolved::id`].
+    pub portal: String,
+    pub auth: AuthState,
+    pub stage: Stage,
+    pub warp_position: Option<Vec3>,
+    pub session: Session,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counters_round_trip_through_their_stored_names() {
+        let counters = Counters { sessions: 3, spawn: 7, chat: 120, ticks: 9000 };
+        let stored: BTreeMap<_, _> = counters.to_stored().into_iter().map(|(name, value)| (name.to_string(), value)).collect();
+        assert_eq!(stored.get("chat_messages"), Some(&120));
+        assert_eq!(Counters::from_stored(&stored), counters);
+        assert_eq!(Counters::from_stored(&BTreeMap::new()), Counters::default());
+    }
 }